@@ -0,0 +1,240 @@
+use embedded_hal_async::i2c::I2c;
+
+use crate::Is31Fl3218 as Driver;
+use crate::{Channel, Error};
+
+/// `N` devices driven as one device with `18 * N` channels
+///
+/// Channel `i` is channel `i % 18` of device `i / 18`. Multi-channel updates write the PWM
+/// registers of all affected devices first and then latch them back-to-back, so the
+/// devices change as close to simultaneously as the bus allows.
+pub struct Is31Fl3218Array<I2C, const N: usize> {
+    chips: [Driver<I2C>; N],
+}
+
+impl<I2C, E, const N: usize> Is31Fl3218Array<I2C, N>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Number of channels of all devices
+    pub const CHANNELS: usize = Channel::COUNT * N;
+
+    /// Drive `chips` in that order
+    pub fn from_chips(chips: [Driver<I2C>; N]) -> Self {
+        Self { chips }
+    }
+
+    /// Destroy the array and return the drivers
+    pub fn release(self) -> [Driver<I2C>; N] {
+        self.chips
+    }
+
+    /// Driver of the `index`th device
+    pub fn chip(&mut self, index: usize) -> Option<&mut Driver<I2C>> {
+        self.chips.get_mut(index)
+    }
+
+    /// Device and channel of a unified channel index
+    fn locate(index: usize) -> Result<(usize, Channel), Error<E>> {
+        let channel = Channel::from_index(index % Channel::COUNT).ok_or(Error::Address)?;
+        if index >= Self::CHANNELS {
+            return Err(Error::Address);
+        }
+        Ok((index / Channel::COUNT, channel))
+    }
+
+    /// Latch the devices `chips` back-to-back
+    async fn latch(&mut self, chips: core::ops::Range<usize>) -> Result<(), Error<E>> {
+        for chip in &mut self.chips[chips] {
+            chip.update().await?;
+        }
+        Ok(())
+    }
+
+    /// Reset all registers of all devices and enable them
+    pub async fn init(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.init(false).await?;
+        }
+        Ok(())
+    }
+
+    /// Enable all devices
+    pub async fn enable_device(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.enable_device().await?;
+        }
+        Ok(())
+    }
+
+    /// Shutdown all devices
+    pub async fn shutdown_device(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.shutdown_device().await?;
+        }
+        Ok(())
+    }
+
+    /// Enable a channel
+    pub async fn enable_channel(&mut self, index: usize) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].enable_channel(channel).await
+    }
+
+    /// Disable a channel
+    pub async fn disable_channel(&mut self, index: usize) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].disable_channel(channel).await
+    }
+
+    /// Enable all channels of all devices
+    pub async fn enable_all(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.enable_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Disable all channels of all devices
+    pub async fn disable_all(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.disable_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Set one channel to a specific brightness value
+    pub async fn set(&mut self, index: usize, brightness: u8) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].set(channel, brightness).await
+    }
+
+    /// Set many consecutive channels to specific brightness values starting at `start`,
+    /// possibly spanning several devices
+    pub async fn set_many(&mut self, start: usize, values: &[u8]) -> Result<(), Error<E>> {
+        if start
+            .checked_add(values.len())
+            .map_or(true, |end| end > Self::CHANNELS)
+        {
+            return Err(Error::Address);
+        }
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut index = start;
+        let mut values = values;
+        while !values.is_empty() {
+            let (chip, channel) = Self::locate(index)?;
+            let len = values.len().min(Channel::COUNT - channel.index());
+            self.chips[chip].stage_many(channel, &values[..len]).await?;
+            index += len;
+            values = &values[len..];
+        }
+        self.latch(start / Channel::COUNT..(index - 1) / Channel::COUNT + 1)
+            .await
+    }
+
+    /// Set all channels of all devices to specific brightness values and enable them
+    /// `values` must hold `18 * N` values
+    pub async fn set_all(&mut self, values: &[u8]) -> Result<(), Error<E>> {
+        if values.len() != Self::CHANNELS {
+            return Err(Error::Address);
+        }
+        for (chip, values) in self
+            .chips
+            .iter_mut()
+            .zip(values.chunks_exact(Channel::COUNT))
+        {
+            let values = values.try_into().map_err(|_| Error::Address)?;
+            chip.stage_all(values).await?;
+        }
+        self.latch(0..N).await
+    }
+
+    /// Reset all registers of all devices
+    pub async fn reset(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.reset().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::mux::{Is31Fl3218Mux, Tca9548a, TCA9548A_ADDRESS};
+    use crate::sim::Simulator;
+
+    /// Index of the first latch and of the last PWM write in the device log
+    fn first_latch_and_last_pwm(sim: &Simulator) -> (usize, usize) {
+        let registers: Vec<u8> = sim.log().iter().map(|bytes| bytes[0]).collect();
+        let first_latch = registers.iter().position(|&register| register == 0x16);
+        let last_pwm = registers
+            .iter()
+            .rposition(|register| (0x01..0x16).contains(register));
+        (first_latch.unwrap(), last_pwm.unwrap())
+    }
+
+    #[test]
+    fn values_span_devices() {
+        let mut first = Simulator::new();
+        let mut second = Simulator::new();
+        let mut array =
+            Is31Fl3218Array::from_chips([Driver::new(&mut first), Driver::new(&mut second)]);
+        block_on(array.init()).unwrap();
+        block_on(array.enable_all()).unwrap();
+        block_on(array.set_many(16, &[1, 2, 3, 4])).unwrap();
+        block_on(array.set(35, 35)).unwrap();
+        assert_eq!(first.duties()[16..], [1, 2]);
+        assert_eq!(second.duties()[..2], [3, 4]);
+        assert_eq!(second.duty(Channel::Out18), 35);
+        assert_eq!(first.log().last().unwrap(), &[0x16, 0]);
+        assert_eq!(second.log().last().unwrap(), &[0x16, 0]);
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let mut first = Simulator::new();
+        let mut second = Simulator::new();
+        let mut array =
+            Is31Fl3218Array::from_chips([Driver::new(&mut first), Driver::new(&mut second)]);
+        assert!(matches!(block_on(array.set(36, 1)), Err(Error::Address)));
+        assert!(matches!(
+            block_on(array.set_many(35, &[1, 2])),
+            Err(Error::Address)
+        ));
+        let result = block_on(array.set_many(usize::MAX, &[1]));
+        assert!(matches!(result, Err(Error::Address)));
+        assert!(matches!(
+            block_on(array.set_all(&[0; 35])),
+            Err(Error::Address)
+        ));
+        assert!(first.log().is_empty() && second.log().is_empty());
+    }
+
+    #[test]
+    fn all_devices_are_staged_before_the_first_latch() {
+        let mut sim = Simulator::new();
+        sim.acknowledge(TCA9548A_ADDRESS);
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut array = Is31Fl3218Mux::new(&mux, [0, 1, 2]).unwrap();
+        block_on(array.set_many(16, &[1; 22])).unwrap();
+        mux.release();
+        let (first_latch, last_pwm) = first_latch_and_last_pwm(&sim);
+        assert!(last_pwm < first_latch);
+        let latches = sim.log().iter().filter(|bytes| bytes[0] == 0x16).count();
+        assert_eq!(latches, 3);
+
+        sim.clear_log();
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut array = Is31Fl3218Mux::new(&mux, [0, 1, 2]).unwrap();
+        block_on(array.set_all(&[9; 54])).unwrap();
+        mux.release();
+        let (first_latch, last_pwm) = first_latch_and_last_pwm(&sim);
+        assert!(last_pwm < first_latch);
+    }
+}
@@ -0,0 +1,604 @@
+//! Blocking driver over `embedded_hal::i2c::I2c`
+//!
+//! Mirrors the async [`crate::Is31Fl3218`] method for method.
+
+mod array;
+mod driver;
+pub mod mux;
+pub mod split;
+pub mod typestate;
+
+pub use array::Is31Fl3218Array;
+pub use driver::LedDriver;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::I2c;
+
+use crate::gamma::{self, GammaTable};
+use crate::rgb::{Rgb, RgbView, PIXELS};
+use crate::shadow::{Shadow, LED_CONTROL, PWM, RESET, SHUTDOWN, UPDATE};
+use crate::{
+    probe_error, Channel, Error, FrameBuffer, NoPin, PowerState, Transaction, DEVICE_ADDRESS,
+    SDB_WAKE_TIME_US,
+};
+
+pub struct Is31Fl3218<I2C, SDB = NoPin> {
+    /// `embedded-hal` compatible I2C instance
+    i2c: I2C,
+    /// `embedded-hal` compatible output pin driving SDB
+    sdb: SDB,
+    /// Whether SDB is driven low
+    hw_shutdown: bool,
+    /// Command buffer
+    cmd_buf: [u8; 23],
+    /// Last values written to the device registers
+    shadow: Shadow,
+    /// Gamma table used by the perceptual setters
+    gamma: &'static [u8],
+    /// Whether failed writes mark the device for a full replay
+    resilient: bool,
+    /// Whether the device has to be brought back to the shadow state
+    resync: bool,
+}
+
+impl<I2C, E> Is31Fl3218<I2C>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance
+    pub fn new(i2c: I2C) -> Self {
+        Self::with_sdb(i2c, NoPin)
+    }
+
+    /// Destroy the driver and return the I2C instance
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<'a, I2C, E> Is31Fl3218<&'a mut I2C>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance borrowing an I2C instance owned elsewhere
+    pub fn new_borrowed(i2c: &'a mut I2C) -> Self {
+        Self::new(i2c)
+    }
+}
+
+impl<I2C, E, SDB> Is31Fl3218<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance with an output pin driving SDB
+    /// The pin is assumed to be high (device not in hardware shutdown)
+    pub fn with_sdb(i2c: I2C, sdb: SDB) -> Self {
+        Self {
+            i2c,
+            sdb,
+            hw_shutdown: false,
+            cmd_buf: [0; 23],
+            shadow: Shadow::new(),
+            gamma: gamma::CIE1931.as_slice(),
+            resilient: false,
+            resync: false,
+        }
+    }
+
+    /// Destroy the driver and return the I2C instance and SDB pin
+    pub fn release_with_sdb(self) -> (I2C, SDB) {
+        (self.i2c, self.sdb)
+    }
+
+    /// Mutable access to the I2C instance, e.g. to talk to other devices on the bus
+    /// Writing to the device directly is not reflected in the shadow registers
+    pub fn i2c_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// Keep the desired state when writes fail and replay all registers once the device
+    /// acknowledges again, e.g. after it was unplugged and came back at power-on defaults
+    ///
+    /// Failed writes still return their error. The replay happens before the next write
+    /// or on [`Self::recover`].
+    pub fn set_resilient(&mut self, resilient: bool) {
+        self.resilient = resilient;
+        self.resync &= resilient;
+    }
+
+    /// Whether the device is known to match the shadow registers
+    pub fn is_synced(&self) -> bool {
+        !self.resync
+    }
+
+    /// Replay all registers before the next write or on [`Self::recover`], e.g. when the
+    /// device may have been power cycled while the driver was idle
+    pub fn mark_unsynced(&mut self) {
+        self.resync = true;
+    }
+
+    /// Replay all registers if a write failed in resilient mode
+    pub fn recover(&mut self) -> Result<(), Error<E>> {
+        if self.resync {
+            self.replay()?;
+            self.resync = false;
+        }
+        Ok(())
+    }
+
+    /// Write all registers from the shadow copy and latch them
+    fn replay(&mut self) -> Result<(), Error<E>> {
+        let (registers, shutdown) = self.shadow.replay();
+        Self::send(&mut self.i2c, &registers)?;
+        Self::send(&mut self.i2c, &shutdown)?;
+        Ok(())
+    }
+
+    /// Write `bytes` to the device, register address first
+    fn send(i2c: &mut I2C, bytes: &[u8]) -> Result<(), E> {
+        #[cfg(feature = "defmt")]
+        defmt::trace!("write {=[u8]:#04x}", bytes);
+        i2c.write(DEVICE_ADDRESS, bytes)
+    }
+
+    fn write_raw(&mut self, len: usize) -> Result<(), Error<E>> {
+        self.recover()?;
+        let result = Self::send(&mut self.i2c, &self.cmd_buf[..=len]);
+        self.resync = result.is_err() && self.resilient;
+        result?;
+        Ok(())
+    }
+
+    fn write(&mut self, register: u8, values: &[u8]) -> Result<(), Error<E>> {
+        let len = values.len();
+        if len > 23 {
+            return Err(Error::Address);
+        }
+        self.cmd_buf[0x0] = register;
+        self.cmd_buf[0x1..=len].copy_from_slice(values);
+        self.write_raw(len)?;
+        Ok(())
+    }
+
+    /// Write `len` registers starting at `register` from the shadow copy
+    fn write_shadow(&mut self, register: u8, len: usize) -> Result<(), Error<E>> {
+        self.cmd_buf[0x0] = register;
+        self.cmd_buf[0x1..=len].copy_from_slice(self.shadow.registers(register, len));
+        self.write_raw(len)?;
+        Ok(())
+    }
+
+    /// Latch the PWM and LED Control Registers
+    pub(crate) fn update(&mut self) -> Result<(), Error<E>> {
+        self.write(UPDATE, &[0])?;
+        Ok(())
+    }
+
+    /// Check whether the device acknowledges its address with a zero-length write
+    /// Returns [`Error::Conn`] if it does not
+    pub fn probe(&mut self) -> Result<(), Error<E>> {
+        self.i2c
+            .write(DEVICE_ADDRESS, &[])
+            .map_err(probe_error::<I2C>)
+    }
+
+    /// Bring the device into a known state: optionally [`Self::probe`] it,
+    /// reset all registers and enable the device
+    pub fn init(&mut self, probe: bool) -> Result<(), Error<E>> {
+        if probe {
+            self.probe()?;
+        }
+        self.reset()?;
+        self.enable_device()?;
+        Ok(())
+    }
+
+    /// Enable the device
+    /// Sets Software Shutdown Enable to Normal operation
+    pub fn enable_device(&mut self) -> Result<(), Error<E>> {
+        self.shadow.set_shutdown(false);
+        self.write_shadow(SHUTDOWN, 1)?;
+        Ok(())
+    }
+
+    /// Shutdown the device
+    /// Sets Software Shutdown Enable to Software shutdown mode
+    pub fn shutdown_device(&mut self) -> Result<(), Error<E>> {
+        self.shadow.set_shutdown(true);
+        self.write_shadow(SHUTDOWN, 1)?;
+        Ok(())
+    }
+
+    /// Current power state as last set through this driver
+    pub fn power_state(&self) -> PowerState {
+        if self.hw_shutdown {
+            PowerState::HardwareShutdown
+        } else if self.shadow.is_shutdown() {
+            PowerState::SoftwareShutdown
+        } else {
+            PowerState::Normal
+        }
+    }
+
+    /// Whether `channel` is enabled as last set through this driver
+    pub fn is_channel_enabled(&self, channel: Channel) -> bool {
+        self.shadow.is_enabled(channel)
+    }
+
+    /// Enable a channel
+    /// Sets the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub fn enable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.set_enabled(channel, true);
+        self.write_shadow(Shadow::control_register(channel), 1)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Enable all channels
+    pub fn enable_all(&mut self) -> Result<(), Error<E>> {
+        self.shadow.set_enabled_all(true);
+        self.write_shadow(LED_CONTROL, 3)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Disable a channel
+    /// Clears the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub fn disable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.set_enabled(channel, false);
+        self.write_shadow(Shadow::control_register(channel), 1)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Disable all channels
+    pub fn disable_all(&mut self) -> Result<(), Error<E>> {
+        self.shadow.set_enabled_all(false);
+        self.write_shadow(LED_CONTROL, 3)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Toggle a channel
+    /// Flips the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub fn toggle_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.toggle_enabled(channel);
+        self.write_shadow(Shadow::control_register(channel), 1)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Enable and disable all channels at once
+    /// Bit 0 of `mask` controls the first channel, bit 17 the last one
+    pub fn set_enable_mask(&mut self, mask: u32) -> Result<(), Error<E>> {
+        if mask >> 18 != 0 {
+            return Err(Error::Address);
+        }
+        self.shadow.set_enable_mask(mask);
+        self.write_shadow(LED_CONTROL, 3)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Set one channel to a specific brightness value
+    pub fn set(&mut self, channel: Channel, brightness: u8) -> Result<(), Error<E>> {
+        self.shadow.set_pwm(channel, brightness);
+        self.write_shadow(Shadow::pwm_register(channel), 1)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Set many consecutive channels to specific brightness values
+    /// starting at `start`
+    pub fn set_many(&mut self, start: Channel, values: &[u8]) -> Result<(), Error<E>> {
+        self.stage_many(start, values)?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// Write brightness values of many consecutive channels without latching them
+    pub(crate) fn stage_many(&mut self, start: Channel, values: &[u8]) -> Result<(), Error<E>> {
+        let len = values.len();
+
+        if start.index() + len > Channel::COUNT {
+            return Err(Error::Address);
+        }
+
+        self.shadow.set_pwm_many(start.index(), values);
+        self.write_shadow(Shadow::pwm_register(start), len)?;
+
+        Ok(())
+    }
+
+    /// Write brightness values of all channels and enable them without latching
+    pub(crate) fn stage_all(&mut self, values: &[u8; 18]) -> Result<(), Error<E>> {
+        self.shadow.set_pwm_many(0, values);
+        self.shadow.set_enabled_all(true);
+        self.write_shadow(PWM, 0x15)?;
+        Ok(())
+    }
+
+    /// Set all channels to specific brightness values and enables all channels
+    pub fn set_all(&mut self, values: &[u8; 18]) -> Result<(), Error<E>> {
+        self.shadow.set_pwm_many(0, values);
+        self.shadow.set_enabled_all(true);
+        self.cmd_buf[0] = PWM;
+        self.cmd_buf[0x1..=0x15].copy_from_slice(self.shadow.registers(PWM, 0x15));
+        self.cmd_buf[0x16] = 0x0;
+        self.write_raw(22)?;
+        Ok(())
+    }
+
+    /// Select the gamma table used by the perceptual setters
+    /// Defaults to [`gamma::CIE1931`]
+    pub fn set_gamma<const N: usize>(&mut self, table: &'static GammaTable<N>) {
+        self.gamma = table.as_slice();
+    }
+
+    /// Set one channel to a perceptual brightness level of the gamma table
+    pub fn set_perceptual(&mut self, channel: Channel, level: u8) -> Result<(), Error<E>> {
+        self.set(channel, gamma::apply(self.gamma, level))
+    }
+
+    /// Set many consecutive channels to perceptual brightness levels of the gamma table
+    /// starting at `start`
+    pub fn set_many_perceptual(&mut self, start: Channel, levels: &[u8]) -> Result<(), Error<E>> {
+        let len = levels.len();
+        if start.index() + len > Channel::COUNT {
+            return Err(Error::Address);
+        }
+        let mut values = [0; 18];
+        for (value, &level) in values.iter_mut().zip(levels) {
+            *value = gamma::apply(self.gamma, level);
+        }
+        self.set_many(start, &values[..len])
+    }
+
+    /// Set all channels to perceptual brightness levels of the gamma table
+    /// and enables all channels
+    pub fn set_all_perceptual(&mut self, levels: &[u8; 18]) -> Result<(), Error<E>> {
+        let values = levels.map(|level| gamma::apply(self.gamma, level));
+        self.set_all(&values)
+    }
+
+    /// Write the brightness values and enable state of a frame
+    /// Only the register ranges that changed since the last write are sent,
+    /// followed by a single latch
+    pub fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Error<E>> {
+        let previous = self.shadow.clone();
+        self.shadow.set_pwm_many(0, frame.pwm());
+        self.shadow.set_enable_mask(frame.enable_mask());
+        // Keep the previous state if a write fails, so the frame is sent again next time
+        let result = self.write_changes(&previous);
+        if result.is_err() {
+            self.shadow = previous;
+        }
+        result
+    }
+
+    /// Write the register ranges that differ from `previous` and latch them
+    fn write_changes(&mut self, previous: &Shadow) -> Result<(), Error<E>> {
+        let mut from = PWM;
+        let mut changed = false;
+        while let Some((register, len)) = self.shadow.next_diff_range(previous, from) {
+            self.write_shadow(register, len)?;
+            from = register + len as u8;
+            changed = true;
+        }
+        if changed {
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    /// Start staging changes that are sent together by [`Transaction::commit`]
+    pub fn begin(&mut self) -> Transaction<'_, Self> {
+        let staged = self.shadow.clone();
+        Transaction::new(self, staged)
+    }
+
+    /// Reset all registers to the default values (same as after a power cycle)
+    pub fn reset(&mut self) -> Result<(), Error<E>> {
+        self.shadow.reset();
+        self.write(RESET, &[0])?;
+        Ok(())
+    }
+}
+
+impl<I2C, E, SDB> Is31Fl3218<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+    SDB: OutputPin,
+{
+    /// Put the device into hardware shutdown by pulling SDB low
+    /// Draws less current than [`Self::shutdown_device`]
+    pub fn hw_shutdown(&mut self) -> Result<(), Error<E>> {
+        self.sdb.set_low().map_err(|_| Error::Pin)?;
+        self.hw_shutdown = true;
+        Ok(())
+    }
+
+    /// Wake the device from hardware shutdown by pulling SDB high
+    /// Waits on `delay` until the device is ready to be accessed
+    pub fn hw_wake(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<E>> {
+        self.sdb.set_high().map_err(|_| Error::Pin)?;
+        delay.delay_us(SDB_WAKE_TIME_US);
+        self.hw_shutdown = false;
+        Ok(())
+    }
+}
+
+impl<I2C, E, SDB> Transaction<'_, Is31Fl3218<I2C, SDB>>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Write all staged changes and latch them with a single Update Register write
+    pub fn commit(self) -> Result<(), Error<E>> {
+        if self.invalid {
+            return Err(Error::Address);
+        }
+        let Some((register, len)) = self.staged.diff_span(&self.driver.shadow) else {
+            return Ok(());
+        };
+        // Keep the previous state if the write fails, so the commit can be retried
+        let previous = core::mem::replace(&mut self.driver.shadow, self.staged);
+        let mut result = self.driver.write_shadow(register, len);
+        if result.is_ok() {
+            result = self.driver.update();
+        }
+        if result.is_err() {
+            self.driver.shadow = previous;
+        }
+        result
+    }
+}
+
+impl<I2C, E, SDB> RgbView<'_, Is31Fl3218<I2C, SDB>>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Set the color of one pixel
+    pub fn set_pixel(&mut self, pixel: usize, color: Rgb) -> Result<(), Error<E>> {
+        if pixel >= PIXELS {
+            return Err(Error::Address);
+        }
+        let mut tx = self.driver.begin();
+        Self::stage(&self.map, &mut tx, pixel, color);
+        tx.commit()
+    }
+
+    /// Set the colors of consecutive pixels starting at `start`
+    pub fn set_pixels(&mut self, start: usize, colors: &[Rgb]) -> Result<(), Error<E>> {
+        if start
+            .checked_add(colors.len())
+            .map_or(true, |end| end > PIXELS)
+        {
+            return Err(Error::Address);
+        }
+        let mut tx = self.driver.begin();
+        for (pixel, &color) in (start..).zip(colors) {
+            Self::stage(&self.map, &mut tx, pixel, color);
+        }
+        tx.commit()
+    }
+
+    /// Set all pixels to the same color
+    pub fn fill(&mut self, color: Rgb) -> Result<(), Error<E>> {
+        self.set_pixels(0, &[color; PIXELS])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::sim::{Fault, Simulator};
+    use crate::{gamma, Channel, ColorOrder, FrameBuffer, PixelMap, Rgb};
+
+    /// Drive every part of the API once and return the bus traffic of both devices
+    macro_rules! exercise {
+        ($run:path) => {
+            pub fn exercise() -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+                let mut sim = Simulator::new();
+                let mut driver = Is31Fl3218::new(&mut sim);
+                $run(driver.init(true)).unwrap();
+                $run(driver.enable_channel(Channel::Out1)).unwrap();
+                $run(driver.enable_all()).unwrap();
+                $run(driver.disable_channel(Channel::Out7)).unwrap();
+                $run(driver.toggle_channel(Channel::Out8)).unwrap();
+                $run(driver.set_enable_mask(0x2_0f0f)).unwrap();
+                $run(driver.set(Channel::Out3, 30)).unwrap();
+                $run(driver.set_many(Channel::Out16, &[1, 2, 3])).unwrap();
+                $run(driver.set_all(&[9; 18])).unwrap();
+                driver.set_gamma(&gamma::DATASHEET_32);
+                $run(driver.set_perceptual(Channel::Out2, 20)).unwrap();
+                $run(driver.set_many_perceptual(Channel::Out4, &[5, 31])).unwrap();
+                $run(driver.set_all_perceptual(&[16; 18])).unwrap();
+                let mut frame = FrameBuffer::new();
+                frame.set(Channel::Out5, 55);
+                frame.set_enabled(Channel::Out6, false);
+                $run(driver.flush(&frame)).unwrap();
+                let mut tx = driver.begin();
+                tx.set(Channel::Out9, 99).toggle_channel(Channel::Out10);
+                $run(tx.commit()).unwrap();
+                let mut rgb = RgbView::new(&mut driver, PixelMap::consecutive(ColorOrder::Grb));
+                $run(rgb.set_pixel(1, Rgb::new(1, 2, 3))).unwrap();
+                $run(rgb.fill(Rgb::new(4, 5, 6))).unwrap();
+                $run(driver.set_brightness(11, 111)).unwrap();
+                $run(driver.set_range(12, &[12, 13])).unwrap();
+                $run(driver.commit()).unwrap();
+                driver.set_resilient(true);
+                driver.i2c_mut().fail_next(Fault::NackData { after: 1 });
+                assert!($run(driver.set(Channel::Out1, 1)).is_err());
+                $run(driver.set(Channel::Out2, 2)).unwrap();
+                $run(driver.shutdown_device()).unwrap();
+                $run(driver.enable_device()).unwrap();
+                $run(driver.reset()).unwrap();
+
+                let Ok(mut active) =
+                    $run(typestate::Is31Fl3218::new(driver.release()).enable_device())
+                else {
+                    panic!("enable failed");
+                };
+                $run(active.enable_all()).unwrap();
+                $run(active.set(Channel::Out1, 1)).unwrap();
+                $run(
+                    active
+                        .rgb(PixelMap::default())
+                        .set_pixel(5, Rgb::new(7, 8, 9)),
+                )
+                .unwrap();
+                let Ok(mut shutdown) = $run(active.shutdown_device()) else {
+                    panic!("shutdown failed");
+                };
+                $run(shutdown.reset()).unwrap();
+
+                let mut other = Simulator::new();
+                let mut array = Is31Fl3218Array::from_chips([
+                    Is31Fl3218::new(&mut sim),
+                    Is31Fl3218::new(&mut other),
+                ]);
+                $run(array.init()).unwrap();
+                $run(array.set_many(16, &[1, 2, 3, 4])).unwrap();
+                $run(array.set_all(&[3; 36])).unwrap();
+                $run(array.set(20, 0)).unwrap();
+                $run(array.disable_all()).unwrap();
+                (sim.log().to_vec(), other.log().to_vec())
+            }
+        };
+    }
+
+    mod nonblocking {
+        use super::*;
+        use crate::{typestate, Is31Fl3218, Is31Fl3218Array, LedDriver, RgbView};
+
+        exercise!(embassy_futures::block_on);
+    }
+
+    mod blocking {
+        use super::*;
+        use crate::blocking::{typestate, Is31Fl3218, Is31Fl3218Array, LedDriver};
+        use crate::RgbView;
+
+        exercise!(core::convert::identity);
+    }
+
+    #[test]
+    fn drivers_write_the_same_registers() {
+        let (blocking, blocking_other) = blocking::exercise();
+        let (nonblocking, nonblocking_other) = nonblocking::exercise();
+        assert_eq!(blocking, nonblocking);
+        assert_eq!(blocking_other, nonblocking_other);
+        assert!(blocking.len() > 40);
+    }
+}
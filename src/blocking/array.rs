@@ -0,0 +1,161 @@
+use embedded_hal::i2c::I2c;
+
+use super::Is31Fl3218 as Driver;
+use crate::{Channel, Error};
+
+/// `N` devices driven as one device with `18 * N` channels
+///
+/// Channel `i` is channel `i % 18` of device `i / 18`. Multi-channel updates write the PWM
+/// registers of all affected devices first and then latch them back-to-back, so the
+/// devices change as close to simultaneously as the bus allows.
+pub struct Is31Fl3218Array<I2C, const N: usize> {
+    chips: [Driver<I2C>; N],
+}
+
+impl<I2C, E, const N: usize> Is31Fl3218Array<I2C, N>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Number of channels of all devices
+    pub const CHANNELS: usize = Channel::COUNT * N;
+
+    /// Drive `chips` in that order
+    pub fn from_chips(chips: [Driver<I2C>; N]) -> Self {
+        Self { chips }
+    }
+
+    /// Destroy the array and return the drivers
+    pub fn release(self) -> [Driver<I2C>; N] {
+        self.chips
+    }
+
+    /// Driver of the `index`th device
+    pub fn chip(&mut self, index: usize) -> Option<&mut Driver<I2C>> {
+        self.chips.get_mut(index)
+    }
+
+    /// Device and channel of a unified channel index
+    fn locate(index: usize) -> Result<(usize, Channel), Error<E>> {
+        let channel = Channel::from_index(index % Channel::COUNT).ok_or(Error::Address)?;
+        if index >= Self::CHANNELS {
+            return Err(Error::Address);
+        }
+        Ok((index / Channel::COUNT, channel))
+    }
+
+    /// Latch the devices `chips` back-to-back
+    fn latch(&mut self, chips: core::ops::Range<usize>) -> Result<(), Error<E>> {
+        for chip in &mut self.chips[chips] {
+            chip.update()?;
+        }
+        Ok(())
+    }
+
+    /// Reset all registers of all devices and enable them
+    pub fn init(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.init(false)?;
+        }
+        Ok(())
+    }
+
+    /// Enable all devices
+    pub fn enable_device(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.enable_device()?;
+        }
+        Ok(())
+    }
+
+    /// Shutdown all devices
+    pub fn shutdown_device(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.shutdown_device()?;
+        }
+        Ok(())
+    }
+
+    /// Enable a channel
+    pub fn enable_channel(&mut self, index: usize) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].enable_channel(channel)
+    }
+
+    /// Disable a channel
+    pub fn disable_channel(&mut self, index: usize) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].disable_channel(channel)
+    }
+
+    /// Enable all channels of all devices
+    pub fn enable_all(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.enable_all()?;
+        }
+        Ok(())
+    }
+
+    /// Disable all channels of all devices
+    pub fn disable_all(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.disable_all()?;
+        }
+        Ok(())
+    }
+
+    /// Set one channel to a specific brightness value
+    pub fn set(&mut self, index: usize, brightness: u8) -> Result<(), Error<E>> {
+        let (chip, channel) = Self::locate(index)?;
+        self.chips[chip].set(channel, brightness)
+    }
+
+    /// Set many consecutive channels to specific brightness values starting at `start`,
+    /// possibly spanning several devices
+    pub fn set_many(&mut self, start: usize, values: &[u8]) -> Result<(), Error<E>> {
+        if start
+            .checked_add(values.len())
+            .map_or(true, |end| end > Self::CHANNELS)
+        {
+            return Err(Error::Address);
+        }
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut index = start;
+        let mut values = values;
+        while !values.is_empty() {
+            let (chip, channel) = Self::locate(index)?;
+            let len = values.len().min(Channel::COUNT - channel.index());
+            self.chips[chip].stage_many(channel, &values[..len])?;
+            index += len;
+            values = &values[len..];
+        }
+        self.latch(start / Channel::COUNT..(index - 1) / Channel::COUNT + 1)
+    }
+
+    /// Set all channels of all devices to specific brightness values and enable them
+    /// `values` must hold `18 * N` values
+    pub fn set_all(&mut self, values: &[u8]) -> Result<(), Error<E>> {
+        if values.len() != Self::CHANNELS {
+            return Err(Error::Address);
+        }
+        for (chip, values) in self
+            .chips
+            .iter_mut()
+            .zip(values.chunks_exact(Channel::COUNT))
+        {
+            let values = values.try_into().map_err(|_| Error::Address)?;
+            chip.stage_all(values)?;
+        }
+        self.latch(0..N)
+    }
+
+    /// Reset all registers of all devices
+    pub fn reset(&mut self) -> Result<(), Error<E>> {
+        for chip in &mut self.chips {
+            chip.reset()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Driver agnostic LED control, see [`crate::LedDriver`]
+
+use embedded_hal::i2c::I2c;
+
+use super::Is31Fl3218 as Driver;
+use crate::rgb::PIXELS;
+use crate::shadow::LED_CONTROL;
+use crate::{Channel, Error, FrameBuffer, GammaTable, PixelMap, PowerState, Rgb};
+
+/// LED driver with individually dimmable channels
+///
+/// Brightness and enable changes are staged and only guaranteed to be visible after
+/// [`Self::commit`], drivers may apply them earlier.
+pub trait LedDriver {
+    /// Error type of the driver
+    type Error;
+
+    /// Number of channels
+    const CHANNELS: usize;
+
+    /// Current power state as last set through this driver
+    fn power_state(&self) -> PowerState;
+
+    /// Enable the device
+    fn enable_device(&mut self) -> Result<(), Self::Error>;
+
+    /// Shutdown the device
+    fn shutdown_device(&mut self) -> Result<(), Self::Error>;
+
+    /// Stage the brightness of one channel
+    fn set_brightness(&mut self, channel: usize, brightness: u8) -> Result<(), Self::Error>;
+
+    /// Stage the brightness of many consecutive channels starting at `start`
+    fn set_range(&mut self, start: usize, values: &[u8]) -> Result<(), Self::Error>;
+
+    /// Stage the enable state of all channels
+    /// Bit 0 of `mask` controls the first channel
+    fn set_enable_mask(&mut self, mask: u32) -> Result<(), Self::Error>;
+
+    /// Apply all staged changes
+    fn commit(&mut self) -> Result<(), Self::Error>;
+
+    /// Write the brightness values and enable state of a frame and commit them
+    /// Channels beyond the frame or the driver are left untouched
+    fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Self::Error> {
+        let len = Self::CHANNELS.min(Channel::COUNT);
+        self.set_range(0, &frame.pwm()[..len])?;
+        self.set_enable_mask(frame.enable_mask())?;
+        self.commit()
+    }
+
+    /// Stage the brightness of one channel to a perceptual `level` of `gamma`
+    fn set_corrected<const N: usize>(
+        &mut self,
+        channel: usize,
+        level: u8,
+        gamma: &GammaTable<N>,
+    ) -> Result<(), Self::Error> {
+        self.set_brightness(channel, gamma.get(level as usize))
+    }
+
+    /// Stage the color of `pixel` wired according to `map`, ignored if out of bounds
+    fn set_pixel(&mut self, map: &PixelMap, pixel: usize, color: Rgb) -> Result<(), Self::Error> {
+        if let Some([r, g, b]) = map.channels(pixel) {
+            self.set_brightness(r.index(), color.r)?;
+            self.set_brightness(g.index(), color.g)?;
+            self.set_brightness(b.index(), color.b)?;
+        }
+        Ok(())
+    }
+
+    /// Stage the colors of consecutive pixels starting at `start`, ignoring those out of
+    /// bounds
+    fn set_pixels(
+        &mut self,
+        map: &PixelMap,
+        start: usize,
+        colors: &[Rgb],
+    ) -> Result<(), Self::Error> {
+        for (pixel, &color) in (start..PIXELS).zip(colors) {
+            self.set_pixel(map, pixel, color)?;
+        }
+        Ok(())
+    }
+}
+
+impl<I2C, E, SDB> LedDriver for Driver<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    type Error = Error<E>;
+
+    const CHANNELS: usize = Channel::COUNT;
+
+    fn power_state(&self) -> PowerState {
+        Driver::power_state(self)
+    }
+
+    fn enable_device(&mut self) -> Result<(), Self::Error> {
+        Driver::enable_device(self)
+    }
+
+    fn shutdown_device(&mut self) -> Result<(), Self::Error> {
+        Driver::shutdown_device(self)
+    }
+
+    fn set_brightness(&mut self, channel: usize, brightness: u8) -> Result<(), Self::Error> {
+        self.set_range(channel, &[brightness])
+    }
+
+    fn set_range(&mut self, start: usize, values: &[u8]) -> Result<(), Self::Error> {
+        let start = Channel::from_index(start).ok_or(Error::Address)?;
+        self.stage_many(start, values)
+    }
+
+    fn set_enable_mask(&mut self, mask: u32) -> Result<(), Self::Error> {
+        if mask >> Channel::COUNT != 0 {
+            return Err(Error::Address);
+        }
+        self.shadow.set_enable_mask(mask);
+        self.write_shadow(LED_CONTROL, 3)?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        self.update()
+    }
+
+    fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Self::Error> {
+        Driver::flush(self, frame)
+    }
+}
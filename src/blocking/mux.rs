@@ -0,0 +1,25 @@
+//! Several devices behind an I2C multiplexer, see [`crate::mux`]
+
+use embedded_hal::i2c::I2c;
+
+use super::{Is31Fl3218 as Driver, Is31Fl3218Array};
+use crate::mux::{check_ports, MuxPort, Tca9548a};
+use crate::Error;
+
+/// Devices behind `N` multiplexer ports driven as one device with `18 * N` channels
+pub type Is31Fl3218Mux<'a, I2C, const N: usize> = Is31Fl3218Array<MuxPort<'a, I2C>, N>;
+
+impl<'a, I2C, E, const N: usize> Is31Fl3218Array<MuxPort<'a, I2C>, N>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Drive the devices behind `ports` of `mux`, in that order
+    /// Returns [`Error::Port`] if a port does not exist or is used twice
+    pub fn new(mux: &'a Tca9548a<I2C>, ports: [u8; N]) -> Result<Self, Error<E>> {
+        check_ports(&ports)?;
+        Ok(Self::from_chips(
+            ports.map(|port| Driver::new(MuxPort { mux, port })),
+        ))
+    }
+}
@@ -0,0 +1,271 @@
+//! Per-channel handles sharing one blocking driver
+//!
+//! A [`SharedIs31Fl3218`] shares a driver between handles for its outputs. A
+//! [`PwmChannel`] implements [`SetDutyCycle`] with a maximum duty cycle of 255, so an
+//! output plugs straight into code expecting a PWM pin:
+//!
+//! ```ignore
+//! driver.init(false)?;
+//! driver.enable_all()?;
+//! let leds = SharedIs31Fl3218::new(driver);
+//! let [red, green, blue, ..] = leds.pwm_channels();
+//! ```
+//!
+//! A [`PinChannel`] implements [`OutputPin`] and [`StatefulOutputPin`] by switching the
+//! enable bit of its channel, keeping the brightness value. Every change is written and
+//! latched right away.
+
+use core::cell::RefCell;
+
+use embedded_hal::digital::{self, OutputPin, StatefulOutputPin};
+use embedded_hal::i2c::I2c;
+use embedded_hal::pwm::{self, SetDutyCycle};
+
+use super::Is31Fl3218 as Driver;
+use crate::{Channel, Error, NoPin};
+
+/// Driver shared between per-channel handles
+pub struct SharedIs31Fl3218<I2C, SDB = NoPin> {
+    driver: RefCell<Driver<I2C, SDB>>,
+}
+
+impl<I2C, SDB> SharedIs31Fl3218<I2C, SDB> {
+    /// Share `driver` between handles
+    pub fn new(driver: Driver<I2C, SDB>) -> Self {
+        Self {
+            driver: RefCell::new(driver),
+        }
+    }
+
+    /// Run `f` on the driver, e.g. to enable the device
+    pub fn with_driver<R>(&self, f: impl FnOnce(&mut Driver<I2C, SDB>) -> R) -> R {
+        f(&mut self.driver.borrow_mut())
+    }
+
+    /// PWM handle of one channel
+    pub fn pwm_channel(&self, channel: Channel) -> PwmChannel<'_, I2C, SDB> {
+        PwmChannel {
+            shared: self,
+            channel,
+        }
+    }
+
+    /// PWM handles of all channels, in channel order
+    pub fn pwm_channels(&self) -> [PwmChannel<'_, I2C, SDB>; Channel::COUNT] {
+        Channel::ALL.map(|channel| self.pwm_channel(channel))
+    }
+
+    /// On/off handle of one channel
+    pub fn pin(&self, channel: Channel) -> PinChannel<'_, I2C, SDB> {
+        PinChannel {
+            shared: self,
+            channel,
+        }
+    }
+
+    /// On/off handles of all channels, in channel order
+    pub fn pins(&self) -> [PinChannel<'_, I2C, SDB>; Channel::COUNT] {
+        Channel::ALL.map(|channel| self.pin(channel))
+    }
+
+    /// Destroy the shared driver and return it
+    pub fn release(self) -> Driver<I2C, SDB> {
+        self.driver.into_inner()
+    }
+}
+
+/// One channel of a [`SharedIs31Fl3218`] as a PWM output
+/// The channel has to be enabled for the duty cycle to show
+pub struct PwmChannel<'a, I2C, SDB> {
+    shared: &'a SharedIs31Fl3218<I2C, SDB>,
+    channel: Channel,
+}
+
+impl<I2C, SDB> PwmChannel<'_, I2C, SDB> {
+    /// The channel driven by this handle
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+}
+
+impl<I2C: I2c, SDB> pwm::ErrorType for PwmChannel<'_, I2C, SDB> {
+    type Error = Error<I2C::Error>;
+}
+
+impl<I2C: I2c, SDB> SetDutyCycle for PwmChannel<'_, I2C, SDB> {
+    fn max_duty_cycle(&self) -> u16 {
+        u8::MAX.into()
+    }
+
+    /// Returns [`Error::Address`] if `duty` exceeds the maximum duty cycle
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        let duty = u8::try_from(duty).map_err(|_| Error::Address)?;
+        self.shared.driver.borrow_mut().set(self.channel, duty)
+    }
+}
+
+/// One channel of a [`SharedIs31Fl3218`] as a digital output
+/// High enables the channel at its configured brightness, low disables it
+pub struct PinChannel<'a, I2C, SDB> {
+    shared: &'a SharedIs31Fl3218<I2C, SDB>,
+    channel: Channel,
+}
+
+impl<I2C, SDB> PinChannel<'_, I2C, SDB> {
+    /// The channel driven by this handle
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+}
+
+impl<I2C: I2c, SDB> digital::ErrorType for PinChannel<'_, I2C, SDB> {
+    type Error = Error<I2C::Error>;
+}
+
+impl<I2C: I2c, SDB> OutputPin for PinChannel<'_, I2C, SDB> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.shared
+            .driver
+            .borrow_mut()
+            .disable_channel(self.channel)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.shared.driver.borrow_mut().enable_channel(self.channel)
+    }
+}
+
+impl<I2C: I2c, SDB> StatefulOutputPin for PinChannel<'_, I2C, SDB> {
+    /// Whether the channel is enabled as last set through the driver
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self
+            .shared
+            .driver
+            .borrow_mut()
+            .is_channel_enabled(self.channel))
+    }
+
+    /// Whether the channel is disabled as last set through the driver
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self
+            .shared
+            .driver
+            .borrow_mut()
+            .is_channel_enabled(self.channel))
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.shared.driver.borrow_mut().toggle_channel(self.channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadow::{LED_CONTROL, PWM, UPDATE};
+    use crate::sim::Simulator;
+
+    fn shared(sim: &mut Simulator) -> SharedIs31Fl3218<&mut Simulator> {
+        let mut driver = Driver::new(sim);
+        driver.init(false).unwrap();
+        driver.enable_all().unwrap();
+        driver.i2c_mut().clear_log();
+        SharedIs31Fl3218::new(driver)
+    }
+
+    #[test]
+    fn duty_cycle_sets_only_its_channel() {
+        let mut sim = Simulator::new();
+        let leds = shared(&mut sim);
+        let [_, mut second, .., mut last] = leds.pwm_channels();
+        assert_eq!(second.max_duty_cycle(), 255);
+        second.set_duty_cycle(200).unwrap();
+        last.set_duty_cycle_fully_on().unwrap();
+        second.set_duty_cycle_percent(50).unwrap();
+        leds.release();
+        assert_eq!(
+            sim.log(),
+            [
+                &[PWM + 1, 200][..],
+                &[UPDATE, 0],
+                &[PWM + 17, 255],
+                &[UPDATE, 0],
+                &[PWM + 1, 127],
+                &[UPDATE, 0],
+            ]
+        );
+        let mut duties = [0; 18];
+        duties[1] = 127;
+        duties[17] = 255;
+        assert_eq!(sim.duties(), duties);
+    }
+
+    #[test]
+    fn duty_cycle_above_maximum_is_rejected() {
+        let mut sim = Simulator::new();
+        let leds = shared(&mut sim);
+        let mut channel = leds.pwm_channel(Channel::Out3);
+        assert!(matches!(channel.set_duty_cycle(256), Err(Error::Address)));
+        leds.release();
+        assert!(sim.log().is_empty());
+    }
+
+    #[test]
+    fn driver_is_usable_between_handles() {
+        let mut sim = Simulator::new();
+        let leds = shared(&mut sim);
+        let mut channel = leds.pwm_channel(Channel::Out1);
+        channel.set_duty_cycle(10).unwrap();
+        leds.with_driver(|driver| driver.shutdown_device()).unwrap();
+        channel.set_duty_cycle(20).unwrap();
+        leds.release();
+        assert!(sim.is_shutdown());
+        assert_eq!(sim.pwm(Channel::Out1), 20);
+    }
+
+    #[test]
+    fn pins_switch_only_their_channel() {
+        let mut sim = Simulator::new();
+        let leds = shared(&mut sim);
+        leds.with_driver(|driver| driver.set_all(&[40; 18]))
+            .unwrap();
+        leds.with_driver(|driver| driver.i2c_mut().clear_log());
+        let mut pin = leds.pin(Channel::Out8);
+        assert!(pin.is_set_high().unwrap());
+        pin.set_low().unwrap();
+        assert!(pin.is_set_low().unwrap());
+        pin.toggle().unwrap();
+        assert!(pin.is_set_high().unwrap());
+        pin.toggle().unwrap();
+        pin.set_high().unwrap();
+        leds.release();
+        assert_eq!(
+            sim.log(),
+            [
+                &[LED_CONTROL + 1, 0x3d][..],
+                &[UPDATE, 0],
+                &[LED_CONTROL + 1, 0x3f],
+                &[UPDATE, 0],
+                &[LED_CONTROL + 1, 0x3d],
+                &[UPDATE, 0],
+                &[LED_CONTROL + 1, 0x3f],
+                &[UPDATE, 0],
+            ]
+        );
+        assert!(Channel::iter().all(|channel| sim.is_enabled(channel)));
+        assert_eq!(sim.duties(), [40; 18]);
+    }
+
+    #[test]
+    fn disabled_pin_keeps_its_duty() {
+        let mut sim = Simulator::new();
+        let leds = shared(&mut sim);
+        leds.pwm_channel(Channel::Out2).set_duty_cycle(90).unwrap();
+        leds.pin(Channel::Out2).set_low().unwrap();
+        leds.release();
+        assert_eq!(sim.pwm(Channel::Out2), 90);
+        assert_eq!(sim.duty(Channel::Out2), 0);
+        assert!(!sim.is_enabled(Channel::Out2));
+        assert!(sim.is_enabled(Channel::Out1) && sim.is_enabled(Channel::Out3));
+    }
+}
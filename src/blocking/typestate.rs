@@ -0,0 +1,185 @@
+//! Typestate wrapper around [`crate::blocking::Is31Fl3218`]
+
+use core::marker::PhantomData;
+
+use embedded_hal::i2c::I2c;
+
+use crate::blocking::Is31Fl3218 as Driver;
+use crate::gamma::GammaTable;
+use crate::rgb::{PixelMap, RgbView};
+use crate::{Channel, Error, FrameBuffer, Transaction};
+
+/// Device in software shutdown, the power-on state
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Shutdown;
+
+/// Device in normal operation
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Active;
+
+/// Driver tracking the power state of the device in its type
+///
+/// Channel operations only exist in the [`Active`] state, state transitions consume the
+/// driver and return it in the new state. A failed transition returns the driver in its
+/// previous state along with the error.
+pub struct Is31Fl3218<I2C, S> {
+    inner: Driver<I2C>,
+    _state: PhantomData<S>,
+}
+
+impl<I2C, S> Is31Fl3218<I2C, S> {
+    fn into_state<T>(self) -> Is31Fl3218<I2C, T> {
+        Is31Fl3218 {
+            inner: self.inner,
+            _state: PhantomData,
+        }
+    }
+
+    /// Unwrap the untyped driver
+    pub fn into_inner(self) -> Driver<I2C> {
+        self.inner
+    }
+}
+
+impl<I2C, E> Is31Fl3218<I2C, Shutdown>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance for a device in its power-on state
+    pub fn new(i2c: I2C) -> Self {
+        Self {
+            inner: Driver::new(i2c),
+            _state: PhantomData,
+        }
+    }
+
+    /// Enable the device
+    /// Sets Software Shutdown Enable to Normal operation
+    pub fn enable_device(mut self) -> Result<Is31Fl3218<I2C, Active>, (Self, Error<E>)> {
+        match self.inner.enable_device() {
+            Ok(()) => Ok(self.into_state()),
+            Err(error) => Err((self, error)),
+        }
+    }
+
+    /// Reset all registers to the default values (same as after a power cycle)
+    pub fn reset(&mut self) -> Result<(), Error<E>> {
+        self.inner.reset()
+    }
+
+    /// Select the gamma table used by the perceptual setters
+    pub fn set_gamma<const N: usize>(&mut self, table: &'static GammaTable<N>) {
+        self.inner.set_gamma(table);
+    }
+}
+
+impl<I2C, E> Is31Fl3218<I2C, Active>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Shutdown the device
+    /// Sets Software Shutdown Enable to Software shutdown mode
+    pub fn shutdown_device(mut self) -> Result<Is31Fl3218<I2C, Shutdown>, (Self, Error<E>)> {
+        match self.inner.shutdown_device() {
+            Ok(()) => Ok(self.into_state()),
+            Err(error) => Err((self, error)),
+        }
+    }
+
+    /// Reset all registers to the default values (same as after a power cycle),
+    /// which puts the device into software shutdown
+    pub fn reset(mut self) -> Result<Is31Fl3218<I2C, Shutdown>, (Self, Error<E>)> {
+        match self.inner.reset() {
+            Ok(()) => Ok(self.into_state()),
+            Err(error) => Err((self, error)),
+        }
+    }
+
+    /// Enable a channel
+    pub fn enable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.inner.enable_channel(channel)
+    }
+
+    /// Enable all channels
+    pub fn enable_all(&mut self) -> Result<(), Error<E>> {
+        self.inner.enable_all()
+    }
+
+    /// Disable a channel
+    pub fn disable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.inner.disable_channel(channel)
+    }
+
+    /// Disable all channels
+    pub fn disable_all(&mut self) -> Result<(), Error<E>> {
+        self.inner.disable_all()
+    }
+
+    /// Toggle a channel
+    pub fn toggle_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.inner.toggle_channel(channel)
+    }
+
+    /// Enable and disable all channels at once
+    /// Bit 0 of `mask` controls the first channel, bit 17 the last one
+    pub fn set_enable_mask(&mut self, mask: u32) -> Result<(), Error<E>> {
+        self.inner.set_enable_mask(mask)
+    }
+
+    /// Set one channel to a specific brightness value
+    pub fn set(&mut self, channel: Channel, brightness: u8) -> Result<(), Error<E>> {
+        self.inner.set(channel, brightness)
+    }
+
+    /// Set many consecutive channels to specific brightness values
+    /// starting at `start`
+    pub fn set_many(&mut self, start: Channel, values: &[u8]) -> Result<(), Error<E>> {
+        self.inner.set_many(start, values)
+    }
+
+    /// Set all channels to specific brightness values and enables all channels
+    pub fn set_all(&mut self, values: &[u8; 18]) -> Result<(), Error<E>> {
+        self.inner.set_all(values)
+    }
+
+    /// Select the gamma table used by the perceptual setters
+    pub fn set_gamma<const N: usize>(&mut self, table: &'static GammaTable<N>) {
+        self.inner.set_gamma(table);
+    }
+
+    /// Set one channel to a perceptual brightness level of the gamma table
+    pub fn set_perceptual(&mut self, channel: Channel, level: u8) -> Result<(), Error<E>> {
+        self.inner.set_perceptual(channel, level)
+    }
+
+    /// Set many consecutive channels to perceptual brightness levels of the gamma table
+    /// starting at `start`
+    pub fn set_many_perceptual(&mut self, start: Channel, levels: &[u8]) -> Result<(), Error<E>> {
+        self.inner.set_many_perceptual(start, levels)
+    }
+
+    /// Set all channels to perceptual brightness levels of the gamma table
+    /// and enables all channels
+    pub fn set_all_perceptual(&mut self, levels: &[u8; 18]) -> Result<(), Error<E>> {
+        self.inner.set_all_perceptual(levels)
+    }
+
+    /// Write the brightness values and enable state of a frame
+    pub fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Error<E>> {
+        self.inner.flush(frame)
+    }
+
+    /// Start staging changes that are sent together by [`Transaction::commit`]
+    pub fn begin(&mut self) -> Transaction<'_, Driver<I2C>> {
+        self.inner.begin()
+    }
+
+    /// View the channels as RGB pixels wired according to `map`
+    pub fn rgb(&mut self, map: PixelMap) -> RgbView<'_, Driver<I2C>> {
+        RgbView::new(&mut self.inner, map)
+    }
+}
@@ -0,0 +1,132 @@
+use core::fmt;
+
+/// One of the 18 LED outputs (OUT1-OUT18)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Channel {
+    Out1 = 0,
+    Out2,
+    Out3,
+    Out4,
+    Out5,
+    Out6,
+    Out7,
+    Out8,
+    Out9,
+    Out10,
+    Out11,
+    Out12,
+    Out13,
+    Out14,
+    Out15,
+    Out16,
+    Out17,
+    Out18,
+}
+
+impl Channel {
+    /// Number of channels of the device
+    pub const COUNT: usize = 18;
+
+    /// All channels in register order
+    pub const ALL: [Channel; Self::COUNT] = [
+        Channel::Out1,
+        Channel::Out2,
+        Channel::Out3,
+        Channel::Out4,
+        Channel::Out5,
+        Channel::Out6,
+        Channel::Out7,
+        Channel::Out8,
+        Channel::Out9,
+        Channel::Out10,
+        Channel::Out11,
+        Channel::Out12,
+        Channel::Out13,
+        Channel::Out14,
+        Channel::Out15,
+        Channel::Out16,
+        Channel::Out17,
+        Channel::Out18,
+    ];
+
+    /// Channel at zero based `index` (0 is OUT1), `None` if out of bounds
+    pub const fn from_index(index: usize) -> Option<Self> {
+        if index < Self::COUNT {
+            Some(Self::ALL[index])
+        } else {
+            None
+        }
+    }
+
+    /// Zero based index of the channel (0 is OUT1)
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Iterate over all channels in register order
+    pub fn iter() -> impl Iterator<Item = Channel> {
+        Self::ALL.into_iter()
+    }
+}
+
+/// Error returned when converting an out of bounds index into a [`Channel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidChannel;
+
+impl fmt::Display for InvalidChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel index out of bounds (0-17)")
+    }
+}
+
+impl core::error::Error for InvalidChannel {}
+
+impl TryFrom<usize> for Channel {
+    type Error = InvalidChannel;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error> {
+        Self::from_index(index).ok_or(InvalidChannel)
+    }
+}
+
+impl TryFrom<u8> for Channel {
+    type Error = InvalidChannel;
+
+    fn try_from(index: u8) -> Result<Self, Self::Error> {
+        Self::from_index(index as usize).ok_or(InvalidChannel)
+    }
+}
+
+impl From<Channel> for usize {
+    fn from(channel: Channel) -> Self {
+        channel.index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_bounds_indices_are_rejected() {
+        assert_eq!(Channel::try_from(17usize), Ok(Channel::Out18));
+        assert_eq!(Channel::try_from(18usize), Err(InvalidChannel));
+        assert_eq!(Channel::try_from(18u8), Err(InvalidChannel));
+        assert_eq!(Channel::try_from(u8::MAX), Err(InvalidChannel));
+    }
+
+    #[test]
+    fn channels_are_in_register_order() {
+        assert_eq!(Channel::ALL[0], Channel::Out1);
+        assert_eq!(Channel::ALL[17], Channel::Out18);
+        for (i, channel) in Channel::iter().enumerate() {
+            assert_eq!(channel.index(), i);
+            assert_eq!(Channel::ALL[i], channel);
+            assert_eq!(usize::from(channel), i);
+        }
+        assert_eq!(Channel::iter().count(), Channel::COUNT);
+    }
+}
@@ -0,0 +1,152 @@
+//! Driver agnostic LED control
+//!
+//! Application code written against [`LedDriver`] runs on any implementation, like
+//! [`Is31Fl3218`](crate::Is31Fl3218), a GPIO PWM fallback or a test double. Frames of
+//! [`Effects`](crate::effects::Effects) are written with [`LedDriver::flush`]:
+//!
+//! ```ignore
+//! async fn run(driver: &mut impl LedDriver, effects: &mut Effects) {
+//!     loop {
+//!         driver.flush(effects.tick(now_ms())).await.ok();
+//!     }
+//! }
+//! ```
+
+use embedded_hal_async::i2c::I2c;
+
+use crate::rgb::PIXELS;
+use crate::shadow::LED_CONTROL;
+use crate::Is31Fl3218 as Driver;
+use crate::{Channel, Error, FrameBuffer, GammaTable, PixelMap, PowerState, Rgb};
+
+/// LED driver with individually dimmable channels
+///
+/// Brightness and enable changes are staged and only guaranteed to be visible after
+/// [`Self::commit`], drivers may apply them earlier.
+#[allow(async_fn_in_trait)]
+pub trait LedDriver {
+    /// Error type of the driver
+    type Error;
+
+    /// Number of channels
+    const CHANNELS: usize;
+
+    /// Current power state as last set through this driver
+    fn power_state(&self) -> PowerState;
+
+    /// Enable the device
+    async fn enable_device(&mut self) -> Result<(), Self::Error>;
+
+    /// Shutdown the device
+    async fn shutdown_device(&mut self) -> Result<(), Self::Error>;
+
+    /// Stage the brightness of one channel
+    async fn set_brightness(&mut self, channel: usize, brightness: u8) -> Result<(), Self::Error>;
+
+    /// Stage the brightness of many consecutive channels starting at `start`
+    async fn set_range(&mut self, start: usize, values: &[u8]) -> Result<(), Self::Error>;
+
+    /// Stage the enable state of all channels
+    /// Bit 0 of `mask` controls the first channel
+    async fn set_enable_mask(&mut self, mask: u32) -> Result<(), Self::Error>;
+
+    /// Apply all staged changes
+    async fn commit(&mut self) -> Result<(), Self::Error>;
+
+    /// Write the brightness values and enable state of a frame and commit them
+    /// Channels beyond the frame or the driver are left untouched
+    async fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Self::Error> {
+        let len = Self::CHANNELS.min(Channel::COUNT);
+        self.set_range(0, &frame.pwm()[..len]).await?;
+        self.set_enable_mask(frame.enable_mask()).await?;
+        self.commit().await
+    }
+
+    /// Stage the brightness of one channel to a perceptual `level` of `gamma`
+    async fn set_corrected<const N: usize>(
+        &mut self,
+        channel: usize,
+        level: u8,
+        gamma: &GammaTable<N>,
+    ) -> Result<(), Self::Error> {
+        self.set_brightness(channel, gamma.get(level as usize))
+            .await
+    }
+
+    /// Stage the color of `pixel` wired according to `map`, ignored if out of bounds
+    async fn set_pixel(
+        &mut self,
+        map: &PixelMap,
+        pixel: usize,
+        color: Rgb,
+    ) -> Result<(), Self::Error> {
+        if let Some([r, g, b]) = map.channels(pixel) {
+            self.set_brightness(r.index(), color.r).await?;
+            self.set_brightness(g.index(), color.g).await?;
+            self.set_brightness(b.index(), color.b).await?;
+        }
+        Ok(())
+    }
+
+    /// Stage the colors of consecutive pixels starting at `start`, ignoring those out of
+    /// bounds
+    async fn set_pixels(
+        &mut self,
+        map: &PixelMap,
+        start: usize,
+        colors: &[Rgb],
+    ) -> Result<(), Self::Error> {
+        for (pixel, &color) in (start..PIXELS).zip(colors) {
+            self.set_pixel(map, pixel, color).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<I2C, E, SDB> LedDriver for Driver<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    type Error = Error<E>;
+
+    const CHANNELS: usize = Channel::COUNT;
+
+    fn power_state(&self) -> PowerState {
+        Driver::power_state(self)
+    }
+
+    async fn enable_device(&mut self) -> Result<(), Self::Error> {
+        Driver::enable_device(self).await
+    }
+
+    async fn shutdown_device(&mut self) -> Result<(), Self::Error> {
+        Driver::shutdown_device(self).await
+    }
+
+    async fn set_brightness(&mut self, channel: usize, brightness: u8) -> Result<(), Self::Error> {
+        self.set_range(channel, &[brightness]).await
+    }
+
+    async fn set_range(&mut self, start: usize, values: &[u8]) -> Result<(), Self::Error> {
+        let start = Channel::from_index(start).ok_or(Error::Address)?;
+        self.stage_many(start, values).await
+    }
+
+    async fn set_enable_mask(&mut self, mask: u32) -> Result<(), Self::Error> {
+        if mask >> Channel::COUNT != 0 {
+            return Err(Error::Address);
+        }
+        self.shadow.set_enable_mask(mask);
+        self.write_shadow(LED_CONTROL, 3).await?;
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), Self::Error> {
+        self.update().await
+    }
+
+    async fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Self::Error> {
+        Driver::flush(self, frame).await
+    }
+}
@@ -0,0 +1,427 @@
+//! Time based LED effects
+//!
+//! [`Effects`] runs one [`Effect`] per channel and renders them into a [`FrameBuffer`]
+//! on every [`Effects::tick`]. Time comes from the caller as milliseconds of any monotonic
+//! clock, so the engine does not depend on a particular executor or timer. All math is
+//! fixed point. Frames can be written to any [`LedDriver`](crate::LedDriver).
+//!
+//! ```ignore
+//! let mut effects = Effects::new();
+//! effects.start(Channel::Out1, Effect::fade(255, 500, Easing::QuadInOut), now_ms());
+//! effects.start(Channel::Out2, Effect::breathe(0, 128, 2000, Easing::SineInOut), now_ms());
+//! loop {
+//!     driver.flush(effects.tick(now_ms())).await?;
+//! }
+//! ```
+
+use crate::{fixed, Channel, FrameBuffer};
+
+/// Fractional bits of the easing progress
+const FRAC_BITS: u32 = 16;
+const ONE: i64 = 1 << FRAC_BITS;
+
+/// Easing curve mapping linear progress to eased progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExponentialIn,
+    ExponentialOut,
+    ExponentialInOut,
+}
+
+impl Easing {
+    /// Eased progress of linear `progress`, both scaled to 0-65536
+    pub fn apply(self, progress: u32) -> u32 {
+        let t = (progress as i64).clamp(0, ONE);
+        if t == 0 || t == ONE {
+            return t as u32;
+        }
+        let (ease_in, mode): (fn(i64) -> i64, _) = match self {
+            Easing::Linear => return t as u32,
+            Easing::QuadIn => (quad, Mode::In),
+            Easing::QuadOut => (quad, Mode::Out),
+            Easing::QuadInOut => (quad, Mode::InOut),
+            Easing::CubicIn => (cubic, Mode::In),
+            Easing::CubicOut => (cubic, Mode::Out),
+            Easing::CubicInOut => (cubic, Mode::InOut),
+            Easing::SineIn => (sine, Mode::In),
+            Easing::SineOut => (sine, Mode::Out),
+            Easing::SineInOut => (sine, Mode::InOut),
+            Easing::ExponentialIn => (exponential, Mode::In),
+            Easing::ExponentialOut => (exponential, Mode::Out),
+            Easing::ExponentialInOut => (exponential, Mode::InOut),
+        };
+        let eased = match mode {
+            Mode::In => ease_in(t),
+            Mode::Out => ONE - ease_in(ONE - t),
+            Mode::InOut if t < ONE / 2 => ease_in(2 * t) / 2,
+            Mode::InOut => ONE - ease_in(2 * (ONE - t)) / 2,
+        };
+        eased.clamp(0, ONE) as u32
+    }
+}
+
+enum Mode {
+    In,
+    Out,
+    InOut,
+}
+
+fn quad(t: i64) -> i64 {
+    t * t / ONE
+}
+
+fn cubic(t: i64) -> i64 {
+    t * t / ONE * t / ONE
+}
+
+/// `1 - cos(t * pi / 2)`
+fn sine(t: i64) -> i64 {
+    // pi / 2 in fixed point
+    const HALF_PI: i64 = 102_944;
+    // cos(x) = sin(pi / 2 - x), Taylor series up to x^7
+    let x = HALF_PI - t * HALF_PI / ONE;
+    let x2 = x * x / ONE;
+    let mut term = x;
+    let mut sin = x;
+    for n in [6, 20, 42] {
+        term = -term * x2 / ONE / n;
+        sin += term;
+    }
+    ONE - sin
+}
+
+/// `2^(10 * t - 10)`, 0 at `t = 0`
+fn exponential(t: i64) -> i64 {
+    if t == 0 {
+        return 0;
+    }
+    let shift = fixed::FRAC_BITS - FRAC_BITS;
+    let exp = ((10 * t - 10 * ONE) as i128) << shift;
+    (fixed::exp2(exp) >> shift) as i64
+}
+
+/// Interpolate between `from` and `to` by eased `progress`
+fn lerp(from: u8, to: u8, progress: u32) -> u8 {
+    let delta = (to as i64 - from as i64) * progress as i64;
+    (from as i64 + (delta + ONE / 2).div_euclid(ONE)) as u8
+}
+
+/// Progress of `elapsed` within `duration` scaled to 0-65536
+fn progress(elapsed: u64, duration: u32) -> u32 {
+    if duration == 0 || elapsed >= duration as u64 {
+        ONE as u32
+    } else {
+        (elapsed * ONE as u64 / duration as u64) as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Kind {
+    Fade { target: u8, duration: u32 },
+    Breathe { low: u8, high: u8, period: u32 },
+    Blink { level: u8, on: u32, off: u32 },
+    Pulse { peak: u8, duration: u32 },
+}
+
+/// Brightness animation of a single channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Effect {
+    kind: Kind,
+    easing: Easing,
+}
+
+impl Effect {
+    /// Fade from the current brightness to `target` within `duration` milliseconds
+    /// Finishes holding `target`
+    pub const fn fade(target: u8, duration: u32, easing: Easing) -> Self {
+        Self {
+            kind: Kind::Fade { target, duration },
+            easing,
+        }
+    }
+
+    /// Fade back and forth between `low` and `high`, one cycle every `period` milliseconds
+    /// Runs until stopped
+    pub const fn breathe(low: u8, high: u8, period: u32, easing: Easing) -> Self {
+        Self {
+            kind: Kind::Breathe { low, high, period },
+            easing,
+        }
+    }
+
+    /// Switch between `level` for `on` milliseconds and off for `off` milliseconds
+    /// Runs until stopped
+    pub const fn blink(level: u8, on: u32, off: u32) -> Self {
+        Self {
+            kind: Kind::Blink { level, on, off },
+            easing: Easing::Linear,
+        }
+    }
+
+    /// Rise from the current brightness to `peak` and fall back within `duration` milliseconds
+    /// Finishes holding the brightness it started from
+    pub const fn pulse(peak: u8, duration: u32, easing: Easing) -> Self {
+        Self {
+            kind: Kind::Pulse { peak, duration },
+            easing,
+        }
+    }
+
+    /// Brightness after `elapsed` milliseconds when started at brightness `from`,
+    /// and whether a finite effect is done
+    fn render(&self, from: u8, elapsed: u64) -> (u8, bool) {
+        match self.kind {
+            Kind::Fade { target, duration } => {
+                let eased = self.easing.apply(progress(elapsed, duration));
+                (lerp(from, target, eased), elapsed >= duration as u64)
+            }
+            Kind::Breathe { low, high, period } => {
+                let phase = elapsed % period.max(1) as u64;
+                let value = Self::triangle(low, high, phase * 2, period, self.easing);
+                (value, false)
+            }
+            Kind::Blink { level, on, off } => {
+                let phase = elapsed % (on as u64 + off as u64).max(1);
+                (if phase < on as u64 { level } else { 0 }, false)
+            }
+            Kind::Pulse { peak, duration } => {
+                if elapsed >= duration as u64 {
+                    return (from, true);
+                }
+                let value = Self::triangle(from, peak, elapsed * 2, duration, self.easing);
+                (value, false)
+            }
+        }
+    }
+
+    /// Up from `low` to `high` for `phase` in the first `duration` and back down in the second
+    fn triangle(low: u8, high: u8, phase: u64, duration: u32, easing: Easing) -> u8 {
+        let phase = if phase < duration as u64 {
+            phase
+        } else {
+            (2 * duration as u64).saturating_sub(phase)
+        };
+        lerp(low, high, easing.apply(progress(phase, duration)))
+    }
+}
+
+/// Running effect of a channel
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    effect: Effect,
+    /// Start time in milliseconds
+    start: u64,
+    /// Brightness when the effect was started
+    from: u8,
+}
+
+/// Effect engine rendering one effect per channel into a [`FrameBuffer`]
+#[derive(Debug, Clone, Default)]
+pub struct Effects {
+    slots: [Option<Slot>; Channel::COUNT],
+    frame: FrameBuffer,
+}
+
+impl Effects {
+    /// Engine without running effects and all channels at brightness 0
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; Channel::COUNT],
+            frame: FrameBuffer::new(),
+        }
+    }
+
+    /// Start `effect` on `channel` at time `now` in milliseconds,
+    /// replacing a running effect of the channel
+    pub fn start(&mut self, channel: Channel, effect: Effect, now: u64) {
+        self.slots[channel.index()] = Some(Slot {
+            effect,
+            start: now,
+            from: self.frame.get(channel),
+        });
+    }
+
+    /// Start `effect` on all channels at time `now` in milliseconds
+    pub fn start_all(&mut self, effect: Effect, now: u64) {
+        for channel in Channel::iter() {
+            self.start(channel, effect, now);
+        }
+    }
+
+    /// Stop the effect of `channel`, keeping its current brightness
+    pub fn stop(&mut self, channel: Channel) {
+        self.slots[channel.index()] = None;
+    }
+
+    /// Stop all effects, keeping the current brightness of all channels
+    pub fn stop_all(&mut self) {
+        self.slots = [None; Channel::COUNT];
+    }
+
+    /// Whether an effect is running on `channel`
+    pub fn is_running(&self, channel: Channel) -> bool {
+        self.slots[channel.index()].is_some()
+    }
+
+    /// Whether no effect is running on any channel
+    pub fn is_idle(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// The frame rendered by the last tick, e.g. to set the brightness of idle channels
+    pub fn frame_mut(&mut self) -> &mut FrameBuffer {
+        &mut self.frame
+    }
+
+    /// Advance all effects to time `now` in milliseconds and return the rendered frame
+    /// Finished effects are removed
+    pub fn tick(&mut self, now: u64) -> &FrameBuffer {
+        for channel in Channel::iter() {
+            let slot = &mut self.slots[channel.index()];
+            if let Some(Slot {
+                effect,
+                start,
+                from,
+            }) = *slot
+            {
+                let (value, done) = effect.render(from, now.saturating_sub(start));
+                self.frame.set(channel, value);
+                if done {
+                    *slot = None;
+                }
+            }
+        }
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 13] = [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::SineIn,
+        Easing::SineOut,
+        Easing::SineInOut,
+        Easing::ExponentialIn,
+        Easing::ExponentialOut,
+        Easing::ExponentialInOut,
+    ];
+
+    #[test]
+    fn easings_hit_endpoints() {
+        for easing in EASINGS {
+            assert_eq!(easing.apply(0), 0, "{easing:?}");
+            assert_eq!(easing.apply(ONE as u32), ONE as u32, "{easing:?}");
+            assert_eq!(easing.apply(u32::MAX), ONE as u32, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn easings_are_monotonic() {
+        for easing in EASINGS {
+            let mut previous = 0;
+            for progress in (0..=ONE as u32).step_by(64) {
+                let eased = easing.apply(progress);
+                assert!(eased >= previous, "{easing:?} at {progress}");
+                previous = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn easings_match_known_values() {
+        let half = ONE as u32 / 2;
+        assert_eq!(Easing::QuadIn.apply(half), 16384);
+        assert_eq!(Easing::CubicOut.apply(half), 57344);
+        assert!(Easing::SineInOut.apply(half).abs_diff(half) <= 1);
+        // 2^-5
+        assert!(Easing::ExponentialIn.apply(half).abs_diff(2048) <= 1);
+        assert!(
+            Easing::ExponentialOut
+                .apply(half)
+                .abs_diff(ONE as u32 - 2048)
+                <= 1
+        );
+    }
+
+    #[test]
+    fn fade_holds_target() {
+        let fade = Effect::fade(200, 1000, Easing::Linear);
+        assert_eq!(fade.render(100, 0), (100, false));
+        assert_eq!(fade.render(100, 500), (150, false));
+        assert_eq!(fade.render(100, 1000), (200, true));
+        assert_eq!(fade.render(100, 5000), (200, true));
+    }
+
+    #[test]
+    fn breathe_wraps_every_period() {
+        let breathe = Effect::breathe(10, 210, 1000, Easing::Linear);
+        assert_eq!(breathe.render(0, 0), (10, false));
+        assert_eq!(breathe.render(0, 250), (110, false));
+        assert_eq!(breathe.render(0, 500), (210, false));
+        assert_eq!(breathe.render(0, 750), (110, false));
+        assert_eq!(breathe.render(0, 1000), (10, false));
+        assert_eq!(breathe.render(0, 3250), (110, false));
+    }
+
+    #[test]
+    fn pulse_returns_to_start() {
+        let pulse = Effect::pulse(250, 1000, Easing::QuadInOut);
+        assert_eq!(pulse.render(50, 0), (50, false));
+        assert_eq!(pulse.render(50, 500), (250, false));
+        assert_eq!(pulse.render(50, 1000), (50, true));
+        assert_eq!(pulse.render(50, 2000), (50, true));
+    }
+
+    #[test]
+    fn blink_alternates() {
+        let blink = Effect::blink(80, 100, 300);
+        assert_eq!(blink.render(0, 99), (80, false));
+        assert_eq!(blink.render(0, 100), (0, false));
+        assert_eq!(blink.render(0, 400), (80, false));
+    }
+
+    #[test]
+    fn zero_durations_do_not_divide_by_zero() {
+        assert_eq!(Effect::fade(9, 0, Easing::CubicIn).render(1, 0), (9, true));
+        assert_eq!(Effect::pulse(9, 0, Easing::Linear).render(1, 0), (1, true));
+        assert_eq!(
+            Effect::breathe(1, 9, 0, Easing::Linear).render(0, 7),
+            (9, false)
+        );
+        assert_eq!(Effect::blink(9, 0, 0).render(0, 7), (0, false));
+    }
+
+    #[test]
+    fn tick_removes_finished_effects() {
+        let mut effects = Effects::new();
+        effects.frame_mut().set(Channel::Out2, 40);
+        effects.start(Channel::Out2, Effect::fade(240, 100, Easing::Linear), 1000);
+        assert_eq!(effects.tick(1050).get(Channel::Out2), 140);
+        assert!(effects.is_running(Channel::Out2));
+        assert_eq!(effects.tick(1100).get(Channel::Out2), 240);
+        assert!(effects.is_idle());
+    }
+}
@@ -0,0 +1,45 @@
+//! Fixed point math shared by the gamma tables and the effect engine
+
+/// Fractional bits of the fixed point numbers
+pub(crate) const FRAC_BITS: u32 = 32;
+pub(crate) const ONE: u128 = 1 << FRAC_BITS;
+
+/// Binary logarithm of `value > 0` as fixed point number
+pub(crate) const fn log2(value: u64) -> i128 {
+    let int = 63 - value.leading_zeros();
+    // Mantissa in [1, 2)
+    let mut mantissa = ((value as u128) << FRAC_BITS) >> int;
+    let mut result = (int as i128) << FRAC_BITS;
+    let mut bit = ONE >> 1;
+    while bit > 0 {
+        mantissa = (mantissa * mantissa) >> FRAC_BITS;
+        if mantissa >= 2 * ONE {
+            mantissa >>= 1;
+            result += bit as i128;
+        }
+        bit >>= 1;
+    }
+    result
+}
+
+/// `2^exp` of a fixed point `exp <= 0` as fixed point number
+pub(crate) const fn exp2(exp: i128) -> u128 {
+    let neg = (-exp) as u128;
+    let shift = (neg >> FRAC_BITS) + 1;
+    if shift > FRAC_BITS as u128 {
+        return 0;
+    }
+    // 2^exp = 2^frac >> shift with frac in (0, 1]
+    let frac = ONE - (neg & (ONE - 1));
+    // e^(frac * ln 2) as Taylor series
+    let x = (frac * 2_977_044_472) >> FRAC_BITS; // ln 2 in fixed point
+    let mut term = ONE;
+    let mut sum = ONE;
+    let mut n = 1;
+    while n < 16 {
+        term = term * x / (n * ONE);
+        sum += term;
+        n += 1;
+    }
+    sum >> shift
+}
@@ -0,0 +1,94 @@
+use crate::Channel;
+
+/// Enable mask with all channels enabled
+const ALL_ENABLED: u32 = (1 << Channel::COUNT) - 1;
+
+/// Brightness and enable state of all channels, written by `flush()` on a driver
+///
+/// Flushing only sends the registers that differ from what was last written to the device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameBuffer {
+    pwm: [u8; Channel::COUNT],
+    enable_mask: u32,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<[u8; Channel::COUNT]> for FrameBuffer {
+    fn from(pwm: [u8; Channel::COUNT]) -> Self {
+        Self {
+            pwm,
+            enable_mask: ALL_ENABLED,
+        }
+    }
+}
+
+impl FrameBuffer {
+    /// All channels enabled at brightness 0
+    pub const fn new() -> Self {
+        Self {
+            pwm: [0; Channel::COUNT],
+            enable_mask: ALL_ENABLED,
+        }
+    }
+
+    /// Brightness value of one channel
+    pub const fn get(&self, channel: Channel) -> u8 {
+        self.pwm[channel.index()]
+    }
+
+    /// Set one channel to a specific brightness value
+    pub fn set(&mut self, channel: Channel, brightness: u8) {
+        self.pwm[channel.index()] = brightness;
+    }
+
+    /// Set all channels to specific brightness values
+    pub fn set_all(&mut self, values: &[u8; Channel::COUNT]) {
+        self.pwm = *values;
+    }
+
+    /// Set all channels to the same brightness value
+    pub fn fill(&mut self, brightness: u8) {
+        self.pwm = [brightness; Channel::COUNT];
+    }
+
+    /// Brightness values of all channels
+    pub const fn pwm(&self) -> &[u8; Channel::COUNT] {
+        &self.pwm
+    }
+
+    /// Mutable brightness values of all channels
+    pub fn pwm_mut(&mut self) -> &mut [u8; Channel::COUNT] {
+        &mut self.pwm
+    }
+
+    /// Whether a channel is enabled
+    pub const fn is_enabled(&self, channel: Channel) -> bool {
+        self.enable_mask & (1 << channel as u32) != 0
+    }
+
+    /// Enable or disable one channel
+    pub fn set_enabled(&mut self, channel: Channel, enabled: bool) {
+        if enabled {
+            self.enable_mask |= 1 << channel as u32;
+        } else {
+            self.enable_mask &= !(1 << channel as u32);
+        }
+    }
+
+    /// Enable state of all channels, bit 0 being the first channel
+    pub const fn enable_mask(&self) -> u32 {
+        self.enable_mask
+    }
+
+    /// Set the enable state of all channels, bit 0 being the first channel
+    /// Bits above bit 17 are ignored
+    pub fn set_enable_mask(&mut self, mask: u32) {
+        self.enable_mask = mask & ALL_ENABLED;
+    }
+}
@@ -0,0 +1,164 @@
+//! Gamma correction for perceptually linear brightness
+//!
+//! The PWM of the device is linear, which makes low brightness steps look like jumps.
+//! A [`GammaTable`] maps perceptual levels to PWM values. All tables are computed at
+//! compile time, applying one is a plain lookup. Any [`LedDriver`](crate::LedDriver)
+//! applies them with [`LedDriver::set_corrected`](crate::LedDriver::set_corrected).
+
+use crate::fixed::{exp2, log2, FRAC_BITS, ONE};
+
+/// Lookup table mapping `N` perceptual levels to PWM values
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GammaTable<const N: usize> {
+    table: [u8; N],
+}
+
+/// 32 step table recommended by the datasheet
+pub static DATASHEET_32: GammaTable<32> = GammaTable::from_table([
+    0, 1, 2, 4, 6, 10, 13, 18, 22, 28, 33, 39, 46, 53, 61, 69, 78, 86, 96, 106, 116, 126, 138, 149,
+    161, 173, 186, 199, 212, 226, 240, 255,
+]);
+
+/// 64 step table recommended by the datasheet
+pub static DATASHEET_64: GammaTable<64> = GammaTable::from_table([
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 18, 20, 22, 24, 26, 29, 32, 35, 38, 41, 44, 47, 50,
+    53, 57, 61, 65, 69, 73, 77, 81, 85, 89, 94, 99, 104, 109, 114, 119, 124, 129, 134, 140, 146,
+    152, 158, 164, 170, 176, 182, 188, 195, 202, 209, 216, 223, 230, 237, 244, 251, 255,
+]);
+
+/// 256 step CIE 1931 lightness curve, the default of the drivers
+pub static CIE1931: GammaTable<256> = GammaTable::cie1931();
+
+impl<const N: usize> GammaTable<N> {
+    /// Use a custom table, `table[level]` being the PWM value of `level`
+    pub const fn from_table(table: [u8; N]) -> Self {
+        assert!(N >= 1, "a gamma table needs at least one level");
+        Self { table }
+    }
+
+    /// CIE 1931 lightness curve with `N` levels
+    pub const fn cie1931() -> Self {
+        assert!(N >= 2, "a gamma table needs at least two levels");
+        let max = (N - 1) as u128;
+        let mut table = [0; N];
+        let mut level = 0;
+        while level < N {
+            let l = level as u128;
+            // Lightness L* = 100 * level / max
+            let (num, den) = if l * 100 <= 8 * max {
+                // Y = L* / 903.3
+                (l * 100 * 255 * 10, max * 9033)
+            } else {
+                // Y = ((L* + 16) / 116)^3
+                let base = 100 * l + 16 * max;
+                let div = 116 * max;
+                (base * base * base * 255, div * div * div)
+            };
+            table[level] = ((num + den / 2) / den) as u8;
+            level += 1;
+        }
+        Self { table }
+    }
+
+    /// Power curve `pwm = 255 * (level / (N - 1))^(numerator / denominator)` with `N` levels
+    ///
+    /// A typical exponent for LEDs is 2.2 to 2.8, e.g. `GammaTable::power(22, 10)`.
+    pub const fn power(numerator: u32, denominator: u32) -> Self {
+        assert!(N >= 2, "a gamma table needs at least two levels");
+        assert!(
+            denominator != 0,
+            "the exponent denominator must not be zero"
+        );
+        let max = (N - 1) as u64;
+        let mut table = [0; N];
+        let mut level = 1;
+        while level < N {
+            // log2(level / max) <= 0, scaled by the exponent
+            let log = log2(level as u64) - log2(max);
+            let exp = log * numerator as i128 / denominator as i128;
+            table[level] = ((exp2(exp) * 255 + ONE / 2) >> FRAC_BITS) as u8;
+            level += 1;
+        }
+        Self { table }
+    }
+
+    /// Number of perceptual levels
+    pub const fn levels(&self) -> usize {
+        N
+    }
+
+    /// PWM value of a perceptual `level`, saturating at the last level
+    pub const fn get(&self, level: usize) -> u8 {
+        if level < N {
+            self.table[level]
+        } else {
+            self.table[N - 1]
+        }
+    }
+
+    /// The PWM values of all levels
+    pub const fn as_slice(&self) -> &[u8] {
+        &self.table
+    }
+}
+
+/// PWM value of a perceptual `level` in `table`, saturating at the last level
+pub(crate) fn apply(table: &[u8], level: u8) -> u8 {
+    table[(level as usize).min(table.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_monotonic(table: &[u8]) -> bool {
+        table.windows(2).all(|pair| pair[0] <= pair[1])
+    }
+
+    #[test]
+    fn power_matches_known_values() {
+        let table = GammaTable::<32>::power(22, 10);
+        assert_eq!(table.as_slice()[..5], [0, 0, 1, 1, 3]);
+        assert_eq!(table.as_slice()[30..], [237, 255]);
+    }
+
+    #[test]
+    fn power_of_one_is_linear() {
+        let table = GammaTable::<256>::power(1, 1);
+        assert!(table
+            .as_slice()
+            .iter()
+            .enumerate()
+            .all(|(i, &v)| v as usize == i));
+    }
+
+    #[test]
+    fn cie1931_spans_full_range() {
+        assert_eq!(CIE1931.get(0), 0);
+        assert_eq!(CIE1931.get(255), 255);
+        assert_eq!(GammaTable::<32>::cie1931().get(31), 255);
+    }
+
+    #[test]
+    fn tables_are_monotonic() {
+        assert!(is_monotonic(CIE1931.as_slice()));
+        assert!(is_monotonic(DATASHEET_32.as_slice()));
+        assert!(is_monotonic(DATASHEET_64.as_slice()));
+        assert!(is_monotonic(GammaTable::<64>::power(28, 10).as_slice()));
+        assert!(is_monotonic(GammaTable::<256>::power(22, 10).as_slice()));
+    }
+
+    #[test]
+    fn levels_saturate() {
+        assert_eq!(DATASHEET_32.get(1000), 255);
+        assert_eq!(apply(DATASHEET_32.as_slice(), 200), 255);
+        assert_eq!(apply(GammaTable::from_table([42]).as_slice(), 3), 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one level")]
+    fn empty_table_is_rejected() {
+        GammaTable::<0>::from_table([]);
+    }
+}
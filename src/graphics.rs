@@ -0,0 +1,123 @@
+//! `embedded-graphics` drawing on LED layouts
+//!
+//! A [`Display`] maps the pixels of a `W` x `H` grid to channels and draws into a buffer
+//! of brightness values, which is written to the device with `set_all`:
+//!
+//! ```ignore
+//! let mut display = Display::<Gray8, 6, 3>::row_major();
+//! Circle::new(Point::zero(), 3)
+//!     .into_styled(PrimitiveStyle::with_fill(Gray8::WHITE))
+//!     .draw(&mut display)?;
+//! driver.set_all(display.values()).await?;
+//! ```
+
+use core::convert::Infallible;
+
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{OriginDimensions, Size};
+use embedded_graphics_core::pixelcolor::{BinaryColor, Gray8, GrayColor, Rgb888, RgbColor};
+use embedded_graphics_core::Pixel;
+
+use crate::Channel;
+
+/// Color that can be shown by the channels of one display pixel
+pub trait LedColor: embedded_graphics_core::pixelcolor::PixelColor {
+    /// Channels of one pixel
+    type Channels: Copy;
+
+    /// Set the brightness values of `channels` to show the color
+    fn write(self, channels: Self::Channels, values: &mut [u8; Channel::COUNT]);
+}
+
+impl LedColor for Gray8 {
+    type Channels = Channel;
+
+    fn write(self, channel: Channel, values: &mut [u8; Channel::COUNT]) {
+        values[channel.index()] = self.luma();
+    }
+}
+
+impl LedColor for BinaryColor {
+    type Channels = Channel;
+
+    fn write(self, channel: Channel, values: &mut [u8; Channel::COUNT]) {
+        values[channel.index()] = if self.is_on() { 0xff } else { 0 };
+    }
+}
+
+impl LedColor for Rgb888 {
+    /// Red, green and blue channel
+    type Channels = [Channel; 3];
+
+    fn write(self, [r, g, b]: [Channel; 3], values: &mut [u8; Channel::COUNT]) {
+        values[r.index()] = self.r();
+        values[g.index()] = self.g();
+        values[b.index()] = self.b();
+    }
+}
+
+/// Draw target on a grid of `W` x `H` pixels with a user defined layout
+pub struct Display<C: LedColor, const W: usize, const H: usize> {
+    /// Channels of each pixel, `None` for positions without LEDs
+    layout: [[Option<C::Channels>; W]; H],
+    values: [u8; Channel::COUNT],
+}
+
+impl<C: LedColor, const W: usize, const H: usize> Display<C, W, H> {
+    /// Display with `layout[y][x]` being the channels of the pixel at `(x, y)`
+    pub const fn new(layout: [[Option<C::Channels>; W]; H]) -> Self {
+        Self {
+            layout,
+            values: [0; Channel::COUNT],
+        }
+    }
+
+    /// Brightness values of all channels, to be written with `set_all`
+    pub const fn values(&self) -> &[u8; Channel::COUNT] {
+        &self.values
+    }
+}
+
+impl<C: LedColor<Channels = Channel>, const W: usize, const H: usize> Display<C, W, H> {
+    /// Pixels mapped to consecutive channels row by row, starting at OUT1 in the top left
+    /// Pixels beyond the last channel are not shown
+    pub const fn row_major() -> Self {
+        let mut layout = [[None; W]; H];
+        let mut y = 0;
+        while y < H {
+            let mut x = 0;
+            while x < W {
+                layout[y][x] = Channel::from_index(y * W + x);
+                x += 1;
+            }
+            y += 1;
+        }
+        Self::new(layout)
+    }
+}
+
+impl<C: LedColor, const W: usize, const H: usize> OriginDimensions for Display<C, W, H> {
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+}
+
+impl<C: LedColor, const W: usize, const H: usize> DrawTarget for Display<C, W, H> {
+    type Color = C;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+            if let Some(Some(channels)) = self.layout.get(y).and_then(|row| row.get(x)) {
+                color.write(*channels, &mut self.values);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,56 @@
 #![cfg_attr(not(test), no_std)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::fmt;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::i2c::{self, Error as _, ErrorKind, NoAcknowledgeSource};
+use embedded_hal_async::delay::DelayNs;
 use embedded_hal_async::i2c::I2c;
 
+mod array;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod channel;
+mod driver;
+pub mod effects;
+mod fixed;
+mod frame;
+pub mod gamma;
+#[cfg(feature = "embedded-graphics")]
+pub mod graphics;
+pub mod mux;
+pub mod retry;
+pub mod rgb;
+mod shadow;
+#[cfg(any(feature = "std", test))]
+pub mod sim;
+#[cfg(feature = "smart-leds")]
+mod smart_leds;
+mod transaction;
+pub mod typestate;
+
+pub use array::Is31Fl3218Array;
+pub use channel::{Channel, InvalidChannel};
+pub use driver::LedDriver;
+pub use frame::FrameBuffer;
+pub use gamma::GammaTable;
+pub use rgb::{ColorOrder, PixelMap, Rgb, RgbView};
+pub use transaction::Transaction;
+
+use rgb::PIXELS;
+use shadow::{Shadow, LED_CONTROL, PWM, RESET, SHUTDOWN, UPDATE};
+
 /// Factory assigned device address
 const DEVICE_ADDRESS: u8 = 0x54;
 
+/// Time the device is given to wake up after SDB is pulled high
+const SDB_WAKE_TIME_US: u32 = 1000;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<I> {
     /// I2C bus error
     I2c(I),
@@ -15,6 +60,8 @@ pub enum Error<I> {
     Address,
     /// Port error (invalid or out of bounds)
     Port,
+    /// SDB pin error, the error of the pin itself is not kept
+    Pin,
 }
 
 impl<I> From<I> for Error<I> {
@@ -23,11 +70,76 @@ impl<I> From<I> for Error<I> {
     }
 }
 
-pub struct Is31Fl3218<I2C> {
+impl<I: fmt::Debug> fmt::Display for Error<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::I2c(error) => write!(f, "I2C bus error: {error:?}"),
+            Error::Conn => write!(f, "device not found"),
+            Error::Address => write!(f, "address out of bounds"),
+            Error::Port => write!(f, "port out of bounds"),
+            Error::Pin => write!(f, "SDB pin error"),
+        }
+    }
+}
+
+impl<I: fmt::Debug> core::error::Error for Error<I> {}
+
+impl<I: fmt::Debug> embedded_hal::digital::Error for Error<I> {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
+impl<I: fmt::Debug> embedded_hal::pwm::Error for Error<I> {
+    fn kind(&self) -> embedded_hal::pwm::ErrorKind {
+        embedded_hal::pwm::ErrorKind::Other
+    }
+}
+
+/// Map an address NACK to [`Error::Conn`], keeping other bus errors
+fn probe_error<I: i2c::ErrorType>(error: I::Error) -> Error<I::Error> {
+    match error.kind() {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address | NoAcknowledgeSource::Unknown) => {
+            Error::Conn
+        }
+        _ => Error::I2c(error),
+    }
+}
+
+/// Placeholder for a driver without SDB pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoPin;
+
+/// Power state of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    /// Normal operation
+    Normal,
+    /// Software shutdown through the Shutdown Register, the power-on default
+    SoftwareShutdown,
+    /// Hardware shutdown through the SDB pin
+    HardwareShutdown,
+}
+
+pub struct Is31Fl3218<I2C, SDB = NoPin> {
     /// `embedded-hal` compatible I2C instance
     i2c: I2C,
+    /// `embedded-hal` compatible output pin driving SDB
+    sdb: SDB,
+    /// Whether SDB is driven low
+    hw_shutdown: bool,
     /// Command buffer
     cmd_buf: [u8; 23],
+    /// Last values written to the device registers
+    shadow: Shadow,
+    /// Gamma table used by the perceptual setters
+    gamma: &'static [u8],
+    /// Whether failed writes mark the device for a full replay
+    resilient: bool,
+    /// Whether the device has to be brought back to the shadow state
+    resync: bool,
 }
 
 impl<I2C, E> Is31Fl3218<I2C>
@@ -37,14 +149,107 @@ where
 {
     /// Create a new Is31Fl3218 instance
     pub fn new(i2c: I2C) -> Self {
+        Self::with_sdb(i2c, NoPin)
+    }
+
+    /// Destroy the driver and return the I2C instance
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<'a, I2C, E> Is31Fl3218<&'a mut I2C>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance borrowing an I2C instance owned elsewhere
+    pub fn new_borrowed(i2c: &'a mut I2C) -> Self {
+        Self::new(i2c)
+    }
+}
+
+impl<I2C, E, SDB> Is31Fl3218<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Create a new Is31Fl3218 instance with an output pin driving SDB
+    /// The pin is assumed to be high (device not in hardware shutdown)
+    pub fn with_sdb(i2c: I2C, sdb: SDB) -> Self {
         Self {
             i2c,
+            sdb,
+            hw_shutdown: false,
             cmd_buf: [0; 23],
+            shadow: Shadow::new(),
+            gamma: gamma::CIE1931.as_slice(),
+            resilient: false,
+            resync: false,
         }
     }
 
+    /// Destroy the driver and return the I2C instance and SDB pin
+    pub fn release_with_sdb(self) -> (I2C, SDB) {
+        (self.i2c, self.sdb)
+    }
+
+    /// Mutable access to the I2C instance, e.g. to talk to other devices on the bus
+    /// Writing to the device directly is not reflected in the shadow registers
+    pub fn i2c_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// Keep the desired state when writes fail and replay all registers once the device
+    /// acknowledges again, e.g. after it was unplugged and came back at power-on defaults
+    ///
+    /// Failed writes still return their error. The replay happens before the next write
+    /// or on [`Self::recover`].
+    pub fn set_resilient(&mut self, resilient: bool) {
+        self.resilient = resilient;
+        self.resync &= resilient;
+    }
+
+    /// Whether the device is known to match the shadow registers
+    pub fn is_synced(&self) -> bool {
+        !self.resync
+    }
+
+    /// Replay all registers before the next write or on [`Self::recover`], e.g. when the
+    /// device may have been power cycled while the driver was idle
+    pub fn mark_unsynced(&mut self) {
+        self.resync = true;
+    }
+
+    /// Replay all registers if a write failed in resilient mode
+    pub async fn recover(&mut self) -> Result<(), Error<E>> {
+        if self.resync {
+            self.replay().await?;
+            self.resync = false;
+        }
+        Ok(())
+    }
+
+    /// Write all registers from the shadow copy and latch them
+    async fn replay(&mut self) -> Result<(), Error<E>> {
+        let (registers, shutdown) = self.shadow.replay();
+        Self::send(&mut self.i2c, &registers).await?;
+        Self::send(&mut self.i2c, &shutdown).await?;
+        Ok(())
+    }
+
+    /// Write `bytes` to the device, register address first
+    async fn send(i2c: &mut I2C, bytes: &[u8]) -> Result<(), E> {
+        #[cfg(feature = "defmt")]
+        defmt::trace!("write {=[u8]:#04x}", bytes);
+        i2c.write(DEVICE_ADDRESS, bytes).await
+    }
+
     async fn write_raw(&mut self, len: usize) -> Result<(), Error<E>> {
-        self.i2c.write(DEVICE_ADDRESS, &self.cmd_buf[..=len]).await?;
+        self.recover().await?;
+        let result = Self::send(&mut self.i2c, &self.cmd_buf[..=len]).await;
+        self.resync = result.is_err() && self.resilient;
+        result?;
         Ok(())
     }
 
@@ -59,78 +264,348 @@ where
         Ok(())
     }
 
+    /// Write `len` registers starting at `register` from the shadow copy
+    async fn write_shadow(&mut self, register: u8, len: usize) -> Result<(), Error<E>> {
+        self.cmd_buf[0x0] = register;
+        self.cmd_buf[0x1..=len].copy_from_slice(self.shadow.registers(register, len));
+        self.write_raw(len).await?;
+        Ok(())
+    }
+
+    /// Latch the PWM and LED Control Registers
+    pub(crate) async fn update(&mut self) -> Result<(), Error<E>> {
+        self.write(UPDATE, &[0]).await?;
+        Ok(())
+    }
+
+    /// Check whether the device acknowledges its address with a zero-length write
+    /// Returns [`Error::Conn`] if it does not
+    pub async fn probe(&mut self) -> Result<(), Error<E>> {
+        self.i2c
+            .write(DEVICE_ADDRESS, &[])
+            .await
+            .map_err(probe_error::<I2C>)
+    }
+
+    /// Bring the device into a known state: optionally [`Self::probe`] it,
+    /// reset all registers and enable the device
+    pub async fn init(&mut self, probe: bool) -> Result<(), Error<E>> {
+        if probe {
+            self.probe().await?;
+        }
+        self.reset().await?;
+        self.enable_device().await?;
+        Ok(())
+    }
+
     /// Enable the device
     /// Sets Software Shutdown Enable to Normal operation
     pub async fn enable_device(&mut self) -> Result<(), Error<E>> {
-        self.write(0x0, &[0x1]).await?;
+        self.shadow.set_shutdown(false);
+        self.write_shadow(SHUTDOWN, 1).await?;
         Ok(())
     }
 
     /// Shutdown the device
     /// Sets Software Shutdown Enable to Software shutdown mode
     pub async fn shutdown_device(&mut self) -> Result<(), Error<E>> {
-        self.write(0x0, &[0]).await?;
+        self.shadow.set_shutdown(true);
+        self.write_shadow(SHUTDOWN, 1).await?;
         Ok(())
     }
 
-    /// Enable a channel
-    /// Sets the corresponding bit in the proper LED Control Register
-    pub async fn enable_channel(&mut self, led: usize) -> Result<(), Error<E>> {
-        if led > 0x11 {
-            return Err(Error::Address);
+    /// Current power state as last set through this driver
+    pub fn power_state(&self) -> PowerState {
+        if self.hw_shutdown {
+            PowerState::HardwareShutdown
+        } else if self.shadow.is_shutdown() {
+            PowerState::SoftwareShutdown
+        } else {
+            PowerState::Normal
         }
-        let register: u8 = 0x13 + led as u8 / 6;
-        let bit: u8 = (led as u8 - 1) % 6;
-        self.write(register, &[1 << bit]).await?;
-        self.write(0x16, &[0]).await?;
+    }
+
+    /// Whether `channel` is enabled as last set through this driver
+    pub fn is_channel_enabled(&self, channel: Channel) -> bool {
+        self.shadow.is_enabled(channel)
+    }
+
+    /// Enable a channel
+    /// Sets the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub async fn enable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.set_enabled(channel, true);
+        self.write_shadow(Shadow::control_register(channel), 1)
+            .await?;
+        self.update().await?;
         Ok(())
     }
 
     /// Enable all channels
     pub async fn enable_all(&mut self) -> Result<(), Error<E>> {
-        self.write(0x13, &[0x3f; 3]).await?;
-        self.write(0x16, &[0]).await?;
+        self.shadow.set_enabled_all(true);
+        self.write_shadow(LED_CONTROL, 3).await?;
+        self.update().await?;
         Ok(())
     }
 
-    /// Set one channel to a specific brightness value
-    pub async fn set(&mut self, led: usize, brightness: u8) -> Result<(), Error<E>> {
-        if led > 0x11 {
+    /// Disable a channel
+    /// Clears the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub async fn disable_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.set_enabled(channel, false);
+        self.write_shadow(Shadow::control_register(channel), 1)
+            .await?;
+        self.update().await?;
+        Ok(())
+    }
+
+    /// Disable all channels
+    pub async fn disable_all(&mut self) -> Result<(), Error<E>> {
+        self.shadow.set_enabled_all(false);
+        self.write_shadow(LED_CONTROL, 3).await?;
+        self.update().await?;
+        Ok(())
+    }
+
+    /// Toggle a channel
+    /// Flips the corresponding bit in the proper LED Control Register,
+    /// leaving the other channels untouched
+    pub async fn toggle_channel(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.shadow.toggle_enabled(channel);
+        self.write_shadow(Shadow::control_register(channel), 1)
+            .await?;
+        self.update().await?;
+        Ok(())
+    }
+
+    /// Enable and disable all channels at once
+    /// Bit 0 of `mask` controls the first channel, bit 17 the last one
+    pub async fn set_enable_mask(&mut self, mask: u32) -> Result<(), Error<E>> {
+        if mask >> 18 != 0 {
             return Err(Error::Address);
         }
-        self.write(0x1 + led as u8, &[brightness]).await?;
-        self.write(0x16, &[0]).await?;
+        self.shadow.set_enable_mask(mask);
+        self.write_shadow(LED_CONTROL, 3).await?;
+        self.update().await?;
         Ok(())
     }
 
-    /// Set many channels to specific brightness values
-    /// `start_led` starts at 0
-    pub async fn set_many(&mut self, start_led: usize, values: &[u8]) -> Result<(), Error<E>> {
+    /// Set one channel to a specific brightness value
+    pub async fn set(&mut self, channel: Channel, brightness: u8) -> Result<(), Error<E>> {
+        self.shadow.set_pwm(channel, brightness);
+        self.write_shadow(Shadow::pwm_register(channel), 1).await?;
+        self.update().await?;
+        Ok(())
+    }
+
+    /// Set many consecutive channels to specific brightness values
+    /// starting at `start`
+    pub async fn set_many(&mut self, start: Channel, values: &[u8]) -> Result<(), Error<E>> {
+        self.stage_many(start, values).await?;
+        self.update().await?;
+        Ok(())
+    }
+
+    /// Write brightness values of many consecutive channels without latching them
+    pub(crate) async fn stage_many(
+        &mut self,
+        start: Channel,
+        values: &[u8],
+    ) -> Result<(), Error<E>> {
         let len = values.len();
 
-        if start_led + len > 0x12 {
+        if start.index() + len > Channel::COUNT {
             return Err(Error::Address);
         }
 
-        self.write(0x1 + start_led as u8, values).await?;
-        self.write(0x16, &[0]).await?;
+        self.shadow.set_pwm_many(start.index(), values);
+        self.write_shadow(Shadow::pwm_register(start), len).await?;
+
+        Ok(())
+    }
 
+    /// Write brightness values of all channels and enable them without latching
+    pub(crate) async fn stage_all(&mut self, values: &[u8; 18]) -> Result<(), Error<E>> {
+        self.shadow.set_pwm_many(0, values);
+        self.shadow.set_enabled_all(true);
+        self.write_shadow(PWM, 0x15).await?;
         Ok(())
     }
 
     /// Set all channels to specific brightness values and enables all channels
     pub async fn set_all(&mut self, values: &[u8; 18]) -> Result<(), Error<E>> {
-        self.cmd_buf[0] = 0x1;
-        self.cmd_buf[0x1..=0x12].copy_from_slice(values);
-        self.cmd_buf[0x13..=0x15].copy_from_slice(&[0x3f; 3]);
+        self.shadow.set_pwm_many(0, values);
+        self.shadow.set_enabled_all(true);
+        self.cmd_buf[0] = PWM;
+        self.cmd_buf[0x1..=0x15].copy_from_slice(self.shadow.registers(PWM, 0x15));
         self.cmd_buf[0x16] = 0x0;
         self.write_raw(22).await?;
         Ok(())
     }
 
+    /// Select the gamma table used by the perceptual setters
+    /// Defaults to [`gamma::CIE1931`]
+    pub fn set_gamma<const N: usize>(&mut self, table: &'static GammaTable<N>) {
+        self.gamma = table.as_slice();
+    }
+
+    /// Set one channel to a perceptual brightness level of the gamma table
+    pub async fn set_perceptual(&mut self, channel: Channel, level: u8) -> Result<(), Error<E>> {
+        self.set(channel, gamma::apply(self.gamma, level)).await
+    }
+
+    /// Set many consecutive channels to perceptual brightness levels of the gamma table
+    /// starting at `start`
+    pub async fn set_many_perceptual(
+        &mut self,
+        start: Channel,
+        levels: &[u8],
+    ) -> Result<(), Error<E>> {
+        let len = levels.len();
+        if start.index() + len > Channel::COUNT {
+            return Err(Error::Address);
+        }
+        let mut values = [0; 18];
+        for (value, &level) in values.iter_mut().zip(levels) {
+            *value = gamma::apply(self.gamma, level);
+        }
+        self.set_many(start, &values[..len]).await
+    }
+
+    /// Set all channels to perceptual brightness levels of the gamma table
+    /// and enables all channels
+    pub async fn set_all_perceptual(&mut self, levels: &[u8; 18]) -> Result<(), Error<E>> {
+        let values = levels.map(|level| gamma::apply(self.gamma, level));
+        self.set_all(&values).await
+    }
+
+    /// Write the brightness values and enable state of a frame
+    /// Only the register ranges that changed since the last write are sent,
+    /// followed by a single latch
+    pub async fn flush(&mut self, frame: &FrameBuffer) -> Result<(), Error<E>> {
+        let previous = self.shadow.clone();
+        self.shadow.set_pwm_many(0, frame.pwm());
+        self.shadow.set_enable_mask(frame.enable_mask());
+        // Keep the previous state if a write fails, so the frame is sent again next time
+        let result = self.write_changes(&previous).await;
+        if result.is_err() {
+            self.shadow = previous;
+        }
+        result
+    }
+
+    /// Write the register ranges that differ from `previous` and latch them
+    async fn write_changes(&mut self, previous: &Shadow) -> Result<(), Error<E>> {
+        let mut from = PWM;
+        let mut changed = false;
+        while let Some((register, len)) = self.shadow.next_diff_range(previous, from) {
+            self.write_shadow(register, len).await?;
+            from = register + len as u8;
+            changed = true;
+        }
+        if changed {
+            self.update().await?;
+        }
+        Ok(())
+    }
+
+    /// Start staging changes that are sent together by [`Transaction::commit`]
+    pub fn begin(&mut self) -> Transaction<'_, Self> {
+        let staged = self.shadow.clone();
+        Transaction::new(self, staged)
+    }
+
     /// Reset all registers to the default values (same as after a power cycle)
     pub async fn reset(&mut self) -> Result<(), Error<E>> {
-        self.write(0x17, &[0]).await?;
+        self.shadow.reset();
+        self.write(RESET, &[0]).await?;
+        Ok(())
+    }
+}
+
+impl<I2C, E, SDB> Is31Fl3218<I2C, SDB>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+    SDB: OutputPin,
+{
+    /// Put the device into hardware shutdown by pulling SDB low
+    /// Draws less current than [`Self::shutdown_device`]
+    pub fn hw_shutdown(&mut self) -> Result<(), Error<E>> {
+        self.sdb.set_low().map_err(|_| Error::Pin)?;
+        self.hw_shutdown = true;
+        Ok(())
+    }
+
+    /// Wake the device from hardware shutdown by pulling SDB high
+    /// Waits on `delay` until the device is ready to be accessed
+    pub async fn hw_wake(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<E>> {
+        self.sdb.set_high().map_err(|_| Error::Pin)?;
+        delay.delay_us(SDB_WAKE_TIME_US).await;
+        self.hw_shutdown = false;
         Ok(())
     }
 }
+
+impl<I2C, E, SDB> Transaction<'_, Is31Fl3218<I2C, SDB>>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Write all staged changes and latch them with a single Update Register write
+    pub async fn commit(self) -> Result<(), Error<E>> {
+        if self.invalid {
+            return Err(Error::Address);
+        }
+        let Some((register, len)) = self.staged.diff_span(&self.driver.shadow) else {
+            return Ok(());
+        };
+        // Keep the previous state if the write fails, so the commit can be retried
+        let previous = core::mem::replace(&mut self.driver.shadow, self.staged);
+        let mut result = self.driver.write_shadow(register, len).await;
+        if result.is_ok() {
+            result = self.driver.update().await;
+        }
+        if result.is_err() {
+            self.driver.shadow = previous;
+        }
+        result
+    }
+}
+
+impl<I2C, E, SDB> RgbView<'_, Is31Fl3218<I2C, SDB>>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Set the color of one pixel
+    pub async fn set_pixel(&mut self, pixel: usize, color: Rgb) -> Result<(), Error<E>> {
+        if pixel >= PIXELS {
+            return Err(Error::Address);
+        }
+        let mut tx = self.driver.begin();
+        Self::stage(&self.map, &mut tx, pixel, color);
+        tx.commit().await
+    }
+
+    /// Set the colors of consecutive pixels starting at `start`
+    pub async fn set_pixels(&mut self, start: usize, colors: &[Rgb]) -> Result<(), Error<E>> {
+        if start
+            .checked_add(colors.len())
+            .map_or(true, |end| end > PIXELS)
+        {
+            return Err(Error::Address);
+        }
+        let mut tx = self.driver.begin();
+        for (pixel, &color) in (start..).zip(colors) {
+            Self::stage(&self.map, &mut tx, pixel, color);
+        }
+        tx.commit().await
+    }
+
+    /// Set all pixels to the same color
+    pub async fn fill(&mut self, color: Rgb) -> Result<(), Error<E>> {
+        self.set_pixels(0, &[color; PIXELS]).await
+    }
+}
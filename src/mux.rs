@@ -0,0 +1,282 @@
+//! Several devices behind a TCA9548A/PCA9548-style I2C multiplexer
+//!
+//! The device address is fixed, so only one device fits on a bus. A [`Tca9548a`] shares
+//! one bus between devices on different multiplexer ports, selecting the port of a
+//! [`MuxPort`] before each of its transactions. [`Is31Fl3218Mux`] drives the devices
+//! behind `N` ports as one [`Is31Fl3218Array`] with `18 * N` channels:
+//!
+//! ```ignore
+//! let mux = Tca9548a::new(i2c, TCA9548A_ADDRESS);
+//! let mut leds = Is31Fl3218Mux::new(&mux, [0, 1, 2])?;
+//! leds.init().await?;
+//! leds.set(40, 255).await?; // OUT5 of the device on port 2
+//! ```
+//!
+//! Ports of the same multiplexer must not be used concurrently from different tasks,
+//! a transaction started while another one is in progress panics.
+
+use core::cell::RefCell;
+
+use embedded_hal::i2c::{self, ErrorType, Operation};
+use embedded_hal_async::i2c::I2c;
+
+use crate::{Error, Is31Fl3218 as Driver, Is31Fl3218Array};
+
+/// Default address of a TCA9548A with all address pins low
+pub const TCA9548A_ADDRESS: u8 = 0x70;
+
+/// Number of ports of a TCA9548A
+pub const PORTS: u8 = 8;
+
+/// Check that all `ports` exist and none is used twice
+pub(crate) fn check_ports<E>(ports: &[u8]) -> Result<(), Error<E>> {
+    for (i, &port) in ports.iter().enumerate() {
+        if port >= PORTS || ports[..i].contains(&port) {
+            return Err(Error::Port);
+        }
+    }
+    Ok(())
+}
+
+/// Shared bus and the port currently selected on the multiplexer
+struct Bus<I2C> {
+    i2c: I2C,
+    selected: Option<u8>,
+}
+
+/// TCA9548A/PCA9548-style I2C multiplexer sharing its bus between ports
+pub struct Tca9548a<I2C> {
+    bus: RefCell<Bus<I2C>>,
+    address: u8,
+}
+
+impl<I2C: ErrorType> Tca9548a<I2C> {
+    /// Multiplexer at `address` on the bus of `i2c`
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            bus: RefCell::new(Bus {
+                i2c,
+                selected: None,
+            }),
+            address,
+        }
+    }
+
+    /// I2C instance talking to devices behind `port`
+    /// Returns [`Error::Port`] if the multiplexer has no such port
+    pub fn port(&self, port: u8) -> Result<MuxPort<'_, I2C>, Error<I2C::Error>> {
+        check_ports(&[port])?;
+        Ok(MuxPort { mux: self, port })
+    }
+
+    /// Forget the selected port, e.g. after the multiplexer was reset
+    pub fn invalidate(&self) {
+        self.bus.borrow_mut().selected = None;
+    }
+
+    /// Destroy the multiplexer and return the I2C instance
+    pub fn release(self) -> I2C {
+        self.bus.into_inner().i2c
+    }
+}
+
+/// One port of a [`Tca9548a`], implements both the blocking and async I2C traits
+pub struct MuxPort<'a, I2C> {
+    pub(crate) mux: &'a Tca9548a<I2C>,
+    pub(crate) port: u8,
+}
+
+impl<I2C> MuxPort<'_, I2C> {
+    /// Index of the port on the multiplexer
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+}
+
+impl<I2C: ErrorType> ErrorType for MuxPort<'_, I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: i2c::I2c> i2c::I2c for MuxPort<'_, I2C> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut bus = self.mux.bus.borrow_mut();
+        let mut result = Ok(());
+        if bus.selected != Some(self.port) {
+            result = bus.i2c.write(self.mux.address, &[1 << self.port]);
+        }
+        if result.is_ok() {
+            bus.selected = Some(self.port);
+            result = bus.i2c.transaction(address, operations);
+        }
+        if result.is_err() {
+            bus.selected = None;
+        }
+        result
+    }
+}
+
+impl<I2C: embedded_hal_async::i2c::I2c> embedded_hal_async::i2c::I2c for MuxPort<'_, I2C> {
+    // Like `embedded_hal_bus::i2c::RefCellDevice`, concurrent use of ports panics
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut bus = self.mux.bus.borrow_mut();
+        let mut result = Ok(());
+        if bus.selected != Some(self.port) {
+            result = bus.i2c.write(self.mux.address, &[1 << self.port]).await;
+        }
+        if result.is_ok() {
+            bus.selected = Some(self.port);
+            result = bus.i2c.transaction(address, operations).await;
+        }
+        if result.is_err() {
+            bus.selected = None;
+        }
+        result
+    }
+}
+
+/// Devices behind `N` multiplexer ports driven as one device with `18 * N` channels
+pub type Is31Fl3218Mux<'a, I2C, const N: usize> = Is31Fl3218Array<MuxPort<'a, I2C>, N>;
+
+impl<'a, I2C, E, const N: usize> Is31Fl3218Array<MuxPort<'a, I2C>, N>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    /// Drive the devices behind `ports` of `mux`, in that order
+    /// Returns [`Error::Port`] if a port does not exist or is used twice
+    pub fn new(mux: &'a Tca9548a<I2C>, ports: [u8; N]) -> Result<Self, Error<E>> {
+        check_ports(&ports)?;
+        Ok(Self::from_chips(
+            ports.map(|port| Driver::new(MuxPort { mux, port })),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::sim::{Fault, SimError, Simulator};
+    use crate::DEVICE_ADDRESS;
+
+    const SELECT: u8 = TCA9548A_ADDRESS;
+
+    fn simulator() -> Simulator {
+        let mut sim = Simulator::new();
+        sim.acknowledge(TCA9548A_ADDRESS);
+        sim
+    }
+
+    /// Write `value` to the first PWM register of the device behind `port`
+    fn write(port: &mut MuxPort<'_, &mut Simulator>, value: u8) -> Result<(), SimError> {
+        i2c::I2c::write(port, DEVICE_ADDRESS, &[0x01, value])
+    }
+
+    fn addresses(sim: &Simulator) -> Vec<u8> {
+        sim.bus_log().iter().map(|(address, _)| *address).collect()
+    }
+
+    #[test]
+    fn port_is_selected_only_when_it_changes() {
+        let mut sim = simulator();
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut two = mux.port(2).unwrap();
+        let mut five = mux.port(5).unwrap();
+        write(&mut two, 1).unwrap();
+        write(&mut two, 2).unwrap();
+        write(&mut five, 3).unwrap();
+        write(&mut two, 4).unwrap();
+        mux.release();
+        let log = sim.bus_log();
+        assert_eq!(log[0], (SELECT, vec![1 << 2]));
+        assert_eq!(log[3], (SELECT, vec![1 << 5]));
+        assert_eq!(log[5], (SELECT, vec![1 << 2]));
+        assert_eq!(
+            addresses(&sim),
+            [
+                SELECT,
+                DEVICE_ADDRESS,
+                DEVICE_ADDRESS,
+                SELECT,
+                DEVICE_ADDRESS,
+                SELECT,
+                DEVICE_ADDRESS
+            ]
+        );
+    }
+
+    #[test]
+    fn failure_clears_the_selection() {
+        let mut sim = simulator();
+        // Fail the first device write and the second select
+        sim.fail_nth(1, Fault::Bus);
+        sim.fail_nth(2, Fault::NackAddress);
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut port = mux.port(1).unwrap();
+        assert!(write(&mut port, 1).is_err());
+        assert!(write(&mut port, 1).is_err());
+        write(&mut port, 1).unwrap();
+        mux.release();
+        assert_eq!(addresses(&sim), [SELECT, SELECT, DEVICE_ADDRESS]);
+        assert_eq!(sim.transaction_count(), 5);
+    }
+
+    #[test]
+    fn invalidate_selects_again() {
+        let mut sim = simulator();
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut port = mux.port(0).unwrap();
+        write(&mut port, 1).unwrap();
+        mux.invalidate();
+        write(&mut port, 1).unwrap();
+        mux.release();
+        assert_eq!(
+            addresses(&sim),
+            [SELECT, DEVICE_ADDRESS, SELECT, DEVICE_ADDRESS]
+        );
+    }
+
+    #[test]
+    fn invalid_ports_are_rejected() {
+        let mut sim = simulator();
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        assert!(matches!(mux.port(PORTS), Err(Error::Port)));
+        assert!(matches!(Is31Fl3218Mux::new(&mux, [0, 8]), Err(Error::Port)));
+        assert!(matches!(
+            Is31Fl3218Mux::new(&mux, [3, 1, 3]),
+            Err(Error::Port)
+        ));
+        assert!(Is31Fl3218Mux::new(&mux, [7, 0, 3]).is_ok());
+    }
+
+    #[test]
+    fn channels_map_to_ports_in_order() {
+        let mut sim = simulator();
+        let mux = Tca9548a::new(&mut sim, TCA9548A_ADDRESS);
+        let mut leds = Is31Fl3218Mux::new(&mux, [4, 6, 1]).unwrap();
+        assert_eq!(Is31Fl3218Mux::<&mut Simulator, 3>::CHANNELS, 54);
+        block_on(leds.set(40, 255)).unwrap();
+        block_on(leds.set(17, 7)).unwrap();
+        block_on(leds.set(18, 8)).unwrap();
+        assert!(matches!(block_on(leds.set(54, 1)), Err(Error::Address)));
+        mux.release();
+        let writes: Vec<_> = sim.bus_log().iter().filter(|(_, b)| b[0] != 0x16).collect();
+        assert_eq!(*writes[0], (SELECT, vec![1 << 1]));
+        assert_eq!(*writes[1], (DEVICE_ADDRESS, vec![0x01 + 4, 255]));
+        assert_eq!(*writes[2], (SELECT, vec![1 << 4]));
+        assert_eq!(*writes[3], (DEVICE_ADDRESS, vec![0x01 + 17, 7]));
+        assert_eq!(*writes[4], (SELECT, vec![1 << 6]));
+        assert_eq!(*writes[5], (DEVICE_ADDRESS, vec![0x01, 8]));
+        assert_eq!(writes.len(), 6);
+    }
+}
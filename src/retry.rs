@@ -0,0 +1,325 @@
+//! Automatic retries of transient I2C failures
+//!
+//! [`RetryI2c`] wraps the I2C instance handed to a driver and repeats every failed
+//! transaction according to a [`RetryPolicy`], so retries apply transparently to all
+//! register writes:
+//!
+//! ```ignore
+//! let policy = RetryPolicy::new(3).with_backoff_us(200);
+//! let mut driver = Is31Fl3218::new(RetryI2c::with_delay(i2c, policy, delay));
+//! driver.set_all(&values).await?;
+//! let stats = driver.i2c_mut().stats();
+//! ```
+
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, Operation};
+
+/// Retry on NACKs, arbitration loss and bus errors
+fn transient(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::NoAcknowledge(_) | ErrorKind::ArbitrationLoss | ErrorKind::Bus
+    )
+}
+
+/// When and how often to retry a failed transaction
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u8,
+    backoff_us: u32,
+    retry_on: fn(ErrorKind) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl RetryPolicy {
+    /// Try every transaction up to `max_attempts` times (at least once)
+    /// on NACKs, arbitration loss and bus errors, without backoff
+    pub const fn new(max_attempts: u8) -> Self {
+        Self {
+            max_attempts,
+            backoff_us: 0,
+            retry_on: transient,
+        }
+    }
+
+    /// Wait `backoff_us` microseconds on the delay of the [`RetryI2c`] before each retry
+    pub const fn with_backoff_us(mut self, backoff_us: u32) -> Self {
+        self.backoff_us = backoff_us;
+        self
+    }
+
+    /// Retry only errors for which `retry_on` returns true
+    pub const fn retry_on(mut self, retry_on: fn(ErrorKind) -> bool) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    /// Whether attempt number `attempt` (counting from 1) failing with `kind` is retried
+    fn should_retry(&self, attempt: u8, kind: ErrorKind) -> bool {
+        attempt < self.max_attempts && (self.retry_on)(kind)
+    }
+}
+
+/// Counters of a [`RetryI2c`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryStats {
+    /// Transactions repeated after a failure
+    pub retries: u32,
+    /// Transactions that failed after all attempts
+    pub failures: u32,
+}
+
+/// Delay that does not wait, for retries without backoff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoDelay;
+
+impl embedded_hal::delay::DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+impl embedded_hal_async::delay::DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// I2C instance retrying failed transactions, implements both the blocking and async traits
+pub struct RetryI2c<I2C, D = NoDelay> {
+    i2c: I2C,
+    delay: D,
+    policy: RetryPolicy,
+    stats: RetryStats,
+}
+
+impl<I2C> RetryI2c<I2C> {
+    /// Retry according to `policy` without backoff
+    pub fn new(i2c: I2C, policy: RetryPolicy) -> Self {
+        Self::with_delay(i2c, policy, NoDelay)
+    }
+}
+
+impl<I2C, D> RetryI2c<I2C, D> {
+    /// Retry according to `policy`, waiting on `delay` for the backoff
+    pub fn with_delay(i2c: I2C, policy: RetryPolicy, delay: D) -> Self {
+        Self {
+            i2c,
+            delay,
+            policy,
+            stats: RetryStats::default(),
+        }
+    }
+
+    /// The policy in effect
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// Change the policy
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// Retries and failures since construction or the last [`Self::reset_stats`]
+    pub fn stats(&self) -> RetryStats {
+        self.stats
+    }
+
+    /// Zero all counters
+    pub fn reset_stats(&mut self) {
+        self.stats = RetryStats::default();
+    }
+
+    /// Destroy the wrapper and return the I2C instance and delay
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+
+    /// Count the outcome of attempt number `attempt`, returns whether to retry
+    fn record<E: i2c::Error>(&mut self, attempt: u8, error: &E) -> bool {
+        if self.policy.should_retry(attempt, error.kind()) {
+            self.stats.retries = self.stats.retries.saturating_add(1);
+            true
+        } else {
+            self.stats.failures = self.stats.failures.saturating_add(1);
+            false
+        }
+    }
+}
+
+impl<I2C: ErrorType, D> ErrorType for RetryI2c<I2C, D> {
+    type Error = I2C::Error;
+}
+
+impl<I2C, D> i2c::I2c for RetryI2c<I2C, D>
+where
+    I2C: i2c::I2c,
+    D: embedded_hal::delay::DelayNs,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut attempt = 1;
+        loop {
+            match self.i2c.transaction(address, operations) {
+                Ok(()) => return Ok(()),
+                Err(error) if self.record(attempt, &error) => {
+                    self.delay.delay_us(self.policy.backoff_us);
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl<I2C, D> embedded_hal_async::i2c::I2c for RetryI2c<I2C, D>
+where
+    I2C: embedded_hal_async::i2c::I2c,
+    D: embedded_hal_async::delay::DelayNs,
+{
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut attempt = 1;
+        loop {
+            match self.i2c.transaction(address, operations).await {
+                Ok(()) => return Ok(()),
+                Err(error) if self.record(attempt, &error) => {
+                    self.delay.delay_us(self.policy.backoff_us).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::i2c::{I2c, NoAcknowledgeSource};
+
+    use super::*;
+    use crate::sim::doubles::TotalDelay;
+    use crate::sim::{Fault, Simulator};
+    use crate::{Channel, Is31Fl3218};
+
+    const ADDRESS: u8 = 0x54;
+
+    fn only_arbitration_loss(kind: ErrorKind) -> bool {
+        kind == ErrorKind::ArbitrationLoss
+    }
+
+    #[test]
+    fn retries_up_to_max_attempts() {
+        let mut sim = Simulator::new();
+        for n in 0..3 {
+            sim.fail_nth(n, Fault::Bus);
+        }
+        let mut i2c = RetryI2c::new(&mut sim, RetryPolicy::new(3));
+        assert!(i2c.write(ADDRESS, &[0x01, 1]).is_err());
+        let stats = i2c.stats();
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(sim.transaction_count(), 3);
+    }
+
+    #[test]
+    fn succeeds_on_last_attempt() {
+        let mut sim = Simulator::new();
+        sim.fail_nth(0, Fault::NackAddress);
+        sim.fail_nth(1, Fault::NackData { after: 2 });
+        let mut i2c = RetryI2c::new(&mut sim, RetryPolicy::new(3));
+        i2c.write(ADDRESS, &[0x01, 1, 2]).unwrap();
+        assert_eq!(
+            i2c.stats(),
+            RetryStats {
+                retries: 2,
+                failures: 0
+            }
+        );
+        assert_eq!(sim.register(0x02), Some(2));
+    }
+
+    #[test]
+    fn zero_and_one_attempts_try_once() {
+        for max_attempts in [0, 1] {
+            let mut sim = Simulator::new();
+            sim.fail_next(Fault::Bus);
+            let mut i2c = RetryI2c::new(&mut sim, RetryPolicy::new(max_attempts));
+            assert!(i2c.write(ADDRESS, &[0x01, 1]).is_err());
+            assert_eq!(
+                i2c.stats(),
+                RetryStats {
+                    retries: 0,
+                    failures: 1
+                }
+            );
+            assert_eq!(sim.transaction_count(), 1);
+        }
+    }
+
+    #[test]
+    fn retries_only_selected_errors() {
+        let policy = RetryPolicy::new(5).retry_on(only_arbitration_loss);
+        let mut sim = Simulator::new();
+        sim.fail_nth(0, Fault::ArbitrationLoss);
+        sim.fail_nth(2, Fault::NackAddress);
+        let mut i2c = RetryI2c::new(&mut sim, policy);
+        i2c.write(ADDRESS, &[0x01, 1]).unwrap();
+        let error = i2c.write(ADDRESS, &[0x01, 1]).unwrap_err();
+        assert_eq!(
+            error.0,
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        assert_eq!(
+            i2c.stats(),
+            RetryStats {
+                retries: 1,
+                failures: 1
+            }
+        );
+        assert_eq!(sim.transaction_count(), 3);
+    }
+
+    #[test]
+    fn waits_backoff_before_each_retry() {
+        let mut sim = Simulator::new();
+        sim.fail_nth(0, Fault::Bus);
+        sim.fail_nth(1, Fault::Bus);
+        let policy = RetryPolicy::new(3).with_backoff_us(200);
+        let mut i2c = RetryI2c::with_delay(&mut sim, policy, TotalDelay::default());
+        i2c.write(ADDRESS, &[0x01, 1]).unwrap();
+        let (_, delay) = i2c.release();
+        assert_eq!(delay.ns, 2 * 200_000);
+    }
+
+    #[test]
+    fn stats_count_random_faults() {
+        let mut sim = Simulator::new();
+        sim.fail_randomly(0.3, Fault::ArbitrationLoss, 7);
+        let mut driver = Is31Fl3218::new(RetryI2c::new(&mut sim, RetryPolicy::new(2)));
+        let mut errors = 0;
+        for value in 0..200 {
+            if embassy_futures::block_on(driver.set(Channel::Out1, value)).is_err() {
+                errors += 1;
+            }
+        }
+        let stats = driver.i2c_mut().stats();
+        assert!(stats.retries > 0 && stats.failures > 0);
+        assert_eq!(stats.failures, errors);
+        // Every transaction either succeeded, was retried or failed for good
+        let succeeded = sim.log().len() as u32;
+        assert_eq!(
+            sim.transaction_count() as u32,
+            succeeded + stats.retries + stats.failures
+        );
+    }
+}
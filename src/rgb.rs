@@ -0,0 +1,172 @@
+//! RGB pixels over channel triplets
+//!
+//! Boards wiring RGB LEDs to the outputs describe the wiring with a [`PixelMap`], an
+//! [`RgbView`] then sets pixel colors on a driver. Every call is sent as one batched
+//! register write followed by a single latch. Other drivers stage pixels through
+//! [`LedDriver::set_pixel`](crate::LedDriver::set_pixel).
+
+use crate::{Channel, Transaction};
+
+/// Number of RGB pixels of one device
+pub const PIXELS: usize = Channel::COUNT / 3;
+
+/// 24 bit color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl From<(u8, u8, u8)> for Rgb {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// Order in which the colors of a pixel are wired to three consecutive channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ColorOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ColorOrder {
+    /// Offsets of the red, green and blue channel within a triplet
+    const fn offsets(self) -> [usize; 3] {
+        match self {
+            ColorOrder::Rgb => [0, 1, 2],
+            ColorOrder::Rbg => [0, 2, 1],
+            ColorOrder::Grb => [1, 0, 2],
+            ColorOrder::Gbr => [2, 0, 1],
+            ColorOrder::Brg => [1, 2, 0],
+            ColorOrder::Bgr => [2, 1, 0],
+        }
+    }
+}
+
+/// Red, green and blue channel of every pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PixelMap {
+    pixels: [[Channel; 3]; PIXELS],
+}
+
+impl PixelMap {
+    /// Arbitrary wiring, `pixels[i]` being the red, green and blue channel of pixel `i`
+    pub const fn new(pixels: [[Channel; 3]; PIXELS]) -> Self {
+        Self { pixels }
+    }
+
+    /// Pixel `i` wired to OUT(3i+1)-OUT(3i+3) with the same color order for all pixels
+    pub const fn consecutive(order: ColorOrder) -> Self {
+        let offsets = order.offsets();
+        let mut pixels = [[Channel::Out1; 3]; PIXELS];
+        let mut pixel = 0;
+        while pixel < PIXELS {
+            let mut color = 0;
+            while color < 3 {
+                pixels[pixel][color] = Channel::ALL[pixel * 3 + offsets[color]];
+                color += 1;
+            }
+            pixel += 1;
+        }
+        Self { pixels }
+    }
+
+    /// Brightness values of all channels showing `colors` on consecutive pixels, e.g. for
+    /// a whole frame written with `set_all` or [`FrameBuffer::from`](crate::FrameBuffer)
+    /// Pixels without a color are off, colors beyond the last pixel are ignored
+    pub fn render(&self, colors: impl IntoIterator<Item = Rgb>) -> [u8; Channel::COUNT] {
+        let mut values = [0; Channel::COUNT];
+        for ([r, g, b], color) in self.pixels.iter().zip(colors) {
+            values[r.index()] = color.r;
+            values[g.index()] = color.g;
+            values[b.index()] = color.b;
+        }
+        values
+    }
+
+    /// Red, green and blue channel of `pixel`, `None` if out of bounds
+    pub const fn channels(&self, pixel: usize) -> Option<[Channel; 3]> {
+        if pixel < PIXELS {
+            Some(self.pixels[pixel])
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for PixelMap {
+    fn default() -> Self {
+        Self::consecutive(ColorOrder::Rgb)
+    }
+}
+
+/// Pixel view on a driver
+pub struct RgbView<'a, D> {
+    pub(crate) driver: &'a mut D,
+    pub(crate) map: PixelMap,
+}
+
+impl<'a, D> RgbView<'a, D> {
+    /// View the channels of `driver` as pixels wired according to `map`
+    pub fn new(driver: &'a mut D, map: PixelMap) -> Self {
+        Self { driver, map }
+    }
+
+    /// The wiring of the pixels
+    pub fn map(&self) -> &PixelMap {
+        &self.map
+    }
+
+    /// Stage the color of `pixel`, ignored if out of bounds
+    pub(crate) fn stage(map: &PixelMap, tx: &mut Transaction<'_, D>, pixel: usize, color: Rgb) {
+        if let Some([r, g, b]) = map.channels(pixel) {
+            tx.set(r, color.r).set(g, color.g).set(b, color.b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Channel::*;
+
+    #[test]
+    fn color_orders_map_wires() {
+        let cases = [
+            (ColorOrder::Rgb, [Out4, Out5, Out6]),
+            (ColorOrder::Rbg, [Out4, Out6, Out5]),
+            (ColorOrder::Grb, [Out5, Out4, Out6]),
+            (ColorOrder::Gbr, [Out6, Out4, Out5]),
+            (ColorOrder::Brg, [Out5, Out6, Out4]),
+            (ColorOrder::Bgr, [Out6, Out5, Out4]),
+        ];
+        for (order, channels) in cases {
+            assert_eq!(PixelMap::consecutive(order).channels(1), Some(channels));
+        }
+        assert_eq!(PixelMap::default().channels(5), Some([Out16, Out17, Out18]));
+        assert_eq!(PixelMap::default().channels(PIXELS), None);
+    }
+
+    #[test]
+    fn render_places_colors() {
+        let map = PixelMap::consecutive(ColorOrder::Grb);
+        let values = map.render([Rgb::new(1, 2, 3), Rgb::new(4, 5, 6)]);
+        assert_eq!(values[..6], [2, 1, 3, 5, 4, 6]);
+        assert_eq!(values[6..], [0; 12]);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::Channel;
+
+/// Number of shadowed registers (Shutdown Register 0x00 up to LED Control Register 0x15)
+pub(crate) const SHADOW_LEN: usize = 0x16;
+
+/// Shutdown Register
+pub(crate) const SHUTDOWN: u8 = 0x00;
+/// First PWM Register (OUT1)
+pub(crate) const PWM: u8 = 0x01;
+/// First LED Control Register (OUT1-OUT6)
+pub(crate) const LED_CONTROL: u8 = 0x13;
+/// Update Register
+pub(crate) const UPDATE: u8 = 0x16;
+/// Reset Register
+pub(crate) const RESET: u8 = 0x17;
+
+/// Copy of the last values written to the (write-only) device registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Shadow {
+    regs: [u8; SHADOW_LEN],
+}
+
+impl Shadow {
+    /// Power-on defaults of all registers
+    pub(crate) const fn new() -> Self {
+        Self {
+            regs: [0; SHADOW_LEN],
+        }
+    }
+
+    /// Restore the power-on defaults
+    pub(crate) fn reset(&mut self) {
+        self.regs = [0; SHADOW_LEN];
+    }
+
+    /// Values of `len` registers starting at `register`
+    pub(crate) fn registers(&self, register: u8, len: usize) -> &[u8] {
+        &self.regs[register as usize..register as usize + len]
+    }
+
+    /// Commands restoring all registers: PWM and LED Control Registers followed by a latch,
+    /// then the Shutdown Register
+    pub(crate) fn replay(&self) -> ([u8; SHADOW_LEN + 1], [u8; 2]) {
+        let mut registers = [0; SHADOW_LEN + 1];
+        registers[0] = PWM;
+        registers[1..SHADOW_LEN].copy_from_slice(&self.regs[PWM as usize..]);
+        // Update Register, any value latches
+        registers[SHADOW_LEN] = 0;
+        (registers, [SHUTDOWN, self.regs[SHUTDOWN as usize]])
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.regs[SHUTDOWN as usize] & 0x1 == 0
+    }
+
+    pub(crate) fn set_shutdown(&mut self, shutdown: bool) {
+        self.regs[SHUTDOWN as usize] = u8::from(!shutdown);
+    }
+
+    pub(crate) fn set_pwm(&mut self, channel: Channel, brightness: u8) {
+        self.regs[PWM as usize + channel.index()] = brightness;
+    }
+
+    pub(crate) fn set_pwm_many(&mut self, start: usize, values: &[u8]) {
+        let start = PWM as usize + start;
+        self.regs[start..start + values.len()].copy_from_slice(values);
+    }
+
+    /// PWM Register of `channel`
+    pub(crate) const fn pwm_register(channel: Channel) -> u8 {
+        PWM + channel as u8
+    }
+
+    /// LED Control Register holding the enable bit of `channel`
+    pub(crate) const fn control_register(channel: Channel) -> u8 {
+        LED_CONTROL + channel as u8 / 6
+    }
+
+    pub(crate) fn is_enabled(&self, channel: Channel) -> bool {
+        self.regs[Self::control_register(channel) as usize] & 1 << (channel.index() % 6) != 0
+    }
+
+    pub(crate) fn set_enabled(&mut self, channel: Channel, enabled: bool) {
+        let register = Self::control_register(channel) as usize;
+        let bit = 1 << (channel.index() % 6);
+        if enabled {
+            self.regs[register] |= bit;
+        } else {
+            self.regs[register] &= !bit;
+        }
+    }
+
+    pub(crate) fn toggle_enabled(&mut self, channel: Channel) {
+        self.regs[Self::control_register(channel) as usize] ^= 1 << (channel.index() % 6);
+    }
+
+    /// Set the enable bits of all channels, bit 0 being OUT1
+    pub(crate) fn set_enable_mask(&mut self, mask: u32) {
+        for (i, reg) in self.regs[LED_CONTROL as usize..SHADOW_LEN]
+            .iter_mut()
+            .enumerate()
+        {
+            *reg = (mask >> (i * 6)) as u8 & 0x3f;
+        }
+    }
+
+    pub(crate) fn set_enabled_all(&mut self, enabled: bool) {
+        let value = if enabled { 0x3f } else { 0 };
+        self.regs[LED_CONTROL as usize..SHADOW_LEN].fill(value);
+    }
+
+    /// Smallest register range (start, len) within the PWM and LED Control Registers
+    /// covering every value that differs from `other`
+    pub(crate) fn diff_span(&self, other: &Shadow) -> Option<(u8, usize)> {
+        let differs = |&r: &usize| self.regs[r] != other.regs[r];
+        let first = (PWM as usize..SHADOW_LEN).find(differs)?;
+        let last = (first..SHADOW_LEN).rev().find(differs)?;
+        Some((first as u8, last - first + 1))
+    }
+
+    /// Next register range (start, len) at or after `from` with values differing from `other`
+    ///
+    /// Ranges separated by no more unchanged registers than the overhead of a separate
+    /// write (address and register byte) are merged.
+    pub(crate) fn next_diff_range(&self, other: &Shadow, from: u8) -> Option<(u8, usize)> {
+        const MAX_GAP: usize = 2;
+        let differs = |r: usize| self.regs[r] != other.regs[r];
+        let first = (from as usize..SHADOW_LEN).find(|&r| differs(r))?;
+        let mut last = first;
+        let mut r = first + 1;
+        while r < SHADOW_LEN && r <= last + MAX_GAP {
+            if differs(r) {
+                last = r;
+            }
+            r += 1;
+        }
+        Some((first as u8, last - first + 1))
+    }
+}
@@ -0,0 +1,735 @@
+//! Software model of an IS31FL3218 for tests without hardware
+//!
+//! [`Simulator`] implements both the blocking and the async `embedded-hal` I2C traits and
+//! decodes every write to the device address into the register model of the chip,
+//! including the update latch, software shutdown and reset.
+//!
+//! Pass `&mut Simulator` to a driver to inspect the simulated device afterwards. Writes to
+//! other devices on the bus, like a multiplexer, are accepted after
+//! [`Simulator::acknowledge`] and show up in [`Simulator::bus_log`].
+//!
+//! Bus failures can be injected on a given transaction with [`Simulator::fail_nth`] or at a
+//! random rate with [`Simulator::fail_randomly`] to exercise error paths.
+
+use std::vec::Vec;
+
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+use crate::shadow::{LED_CONTROL, PWM, RESET, SHADOW_LEN, SHUTDOWN, UPDATE};
+use crate::{Channel, DEVICE_ADDRESS};
+
+/// Error returned by the simulated bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimError(pub ErrorKind);
+
+impl i2c::Error for SimError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+/// Bus failure injected into a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The device does not acknowledge its address, nothing is written
+    NackAddress,
+    /// The device acknowledges the first `after` bytes, register address included, and
+    /// does not acknowledge the next one
+    /// The acknowledged bytes take effect, so `after: 0` writes nothing and `after: 3`
+    /// sets the register address and writes two registers.
+    NackData { after: usize },
+    /// Arbitration is lost to another controller, nothing is written
+    ArbitrationLoss,
+    /// Misplaced start or stop condition, nothing is written
+    Bus,
+}
+
+impl Fault {
+    /// Error reported for the fault
+    pub fn kind(self) -> ErrorKind {
+        match self {
+            Fault::NackAddress => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            Fault::NackData { .. } => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+            Fault::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+            Fault::Bus => ErrorKind::Bus,
+        }
+    }
+}
+
+/// Random failures at a fixed rate
+#[derive(Debug, Clone)]
+struct RandomFaults {
+    fault: Fault,
+    /// Failure probability scaled to `u32::MAX`
+    threshold: u32,
+    /// xorshift32 state
+    state: u32,
+}
+
+impl RandomFaults {
+    fn next(&mut self) -> Option<Fault> {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state < self.threshold).then_some(self.fault)
+    }
+}
+
+/// Virtual IS31FL3218 on a virtual I2C bus
+#[derive(Debug, Clone, Default)]
+pub struct Simulator {
+    /// Registers 0x00-0x15 as written
+    registers: [u8; SHADOW_LEN],
+    /// PWM and LED Control Registers as loaded by the last update
+    latched: [u8; SHADOW_LEN],
+    /// Payload of every write transaction to the device, register address first
+    log: Vec<Vec<u8>>,
+    /// Number of transactions seen on the bus, including failed ones
+    transactions: usize,
+    /// Faults scheduled for specific transactions
+    scheduled: Vec<(usize, Fault)>,
+    /// Faults injected at random
+    random: Option<RandomFaults>,
+    /// Whether the device is detached from the bus
+    detached: bool,
+    /// Addresses of other devices acknowledging writes
+    acknowledged: Vec<u8>,
+    /// Address and payload of every write on the bus
+    bus_log: Vec<(u8, Vec<u8>)>,
+}
+
+impl Simulator {
+    /// Device in its power-on state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the device is in software shutdown
+    pub fn is_shutdown(&self) -> bool {
+        self.registers[SHUTDOWN as usize] & 0x1 == 0
+    }
+
+    /// Value of register 0x00-0x15 as written, before latching
+    /// `None` for the Update and Reset Registers and beyond, which hold no value
+    pub fn register(&self, register: u8) -> Option<u8> {
+        self.registers.get(register as usize).copied()
+    }
+
+    /// PWM value of a channel in effect since the last update
+    pub fn pwm(&self, channel: Channel) -> u8 {
+        self.latched[PWM as usize + channel.index()]
+    }
+
+    /// Whether a channel is enabled since the last update
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        let register = LED_CONTROL as usize + channel.index() / 6;
+        self.latched[register] & (1 << (channel.index() % 6)) != 0
+    }
+
+    /// Effective output duty of a channel, 0 when disabled or shut down
+    pub fn duty(&self, channel: Channel) -> u8 {
+        if self.is_shutdown() || !self.is_enabled(channel) {
+            0
+        } else {
+            self.pwm(channel)
+        }
+    }
+
+    /// Effective output duty of all channels
+    pub fn duties(&self) -> [u8; Channel::COUNT] {
+        Channel::ALL.map(|channel| self.duty(channel))
+    }
+
+    /// Payload of every write transaction to the device, register address first
+    /// Writes failing with [`Fault::NackData`] are logged up to the acknowledged bytes
+    pub fn log(&self) -> &[Vec<u8>] {
+        &self.log
+    }
+
+    /// Address and payload of every write on the bus, including those to other devices
+    pub fn bus_log(&self) -> &[(u8, Vec<u8>)] {
+        &self.bus_log
+    }
+
+    /// Forget all logged transactions
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+        self.bus_log.clear();
+    }
+
+    /// Acknowledge and log writes to another device at `address`, e.g. a multiplexer
+    pub fn acknowledge(&mut self, address: u8) {
+        self.acknowledged.push(address);
+    }
+
+    /// Number of transactions seen on the bus so far, including failed ones
+    pub fn transaction_count(&self) -> usize {
+        self.transactions
+    }
+
+    /// Fail transaction number `n`, counting from 0 for the first transaction on the bus
+    pub fn fail_nth(&mut self, n: usize, fault: Fault) {
+        self.scheduled.push((n, fault));
+    }
+
+    /// Fail the next transaction on the bus
+    pub fn fail_next(&mut self, fault: Fault) {
+        self.fail_nth(self.transactions, fault);
+    }
+
+    /// Fail transactions at random with probability `rate` (0.0 to 1.0)
+    /// The same `seed` reproduces the same sequence of failures
+    pub fn fail_randomly(&mut self, rate: f32, fault: Fault, seed: u32) {
+        self.random = Some(RandomFaults {
+            fault,
+            threshold: (rate.clamp(0.0, 1.0) as f64 * u32::MAX as f64) as u32,
+            // xorshift has a fixed point at 0
+            state: seed.max(1),
+        });
+    }
+
+    /// Detach the device from the bus, it does not acknowledge its address until reattached
+    pub fn detach(&mut self) {
+        self.detached = true;
+    }
+
+    /// Reattach the device, which comes back in its power-on state
+    pub fn attach(&mut self) {
+        self.detached = false;
+        self.registers = [0; SHADOW_LEN];
+        self.latched = [0; SHADOW_LEN];
+    }
+
+    /// Remove all scheduled and random faults
+    pub fn clear_faults(&mut self) {
+        self.scheduled.clear();
+        self.random = None;
+    }
+
+    /// Fault injected into the current transaction, if any
+    fn fault(&mut self) -> Option<Fault> {
+        let n = self.transactions;
+        self.transactions += 1;
+        let scheduled = self
+            .scheduled
+            .iter()
+            .position(|&(at, _)| at == n)
+            .map(|i| self.scheduled.swap_remove(i).1);
+        let random = self.random.as_mut().and_then(RandomFaults::next);
+        scheduled.or(random)
+    }
+
+    /// Decode a write starting with the register address, auto incrementing afterwards
+    fn decode(&mut self, bytes: &[u8]) {
+        let Some((&start, values)) = bytes.split_first() else {
+            return;
+        };
+        for (register, &value) in (start as usize..).zip(values) {
+            match register as u8 {
+                UPDATE => {
+                    self.latched[PWM as usize..].copy_from_slice(&self.registers[PWM as usize..])
+                }
+                RESET => {
+                    self.registers = [0; SHADOW_LEN];
+                    self.latched = [0; SHADOW_LEN];
+                }
+                _ if register < SHADOW_LEN => self.registers[register] = value,
+                _ => {}
+            }
+        }
+        self.log.push(bytes.to_vec());
+    }
+
+    fn process(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), SimError> {
+        let fault = self.fault();
+        if let Some(fault) = fault.filter(|fault| !matches!(fault, Fault::NackData { .. })) {
+            return Err(SimError(fault.kind()));
+        }
+        let device = address == DEVICE_ADDRESS && !self.detached;
+        if !device && !self.acknowledged.contains(&address) {
+            return Err(SimError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Address,
+            )));
+        }
+        // The devices are write only and do not acknowledge reads
+        if operations
+            .iter()
+            .any(|operation| matches!(operation, Operation::Read(_)))
+        {
+            return Err(SimError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Address,
+            )));
+        }
+        // Adjacent writes are sent without a repeated start and form a single write
+        let bytes: Vec<u8> = operations
+            .iter()
+            .flat_map(|operation| match operation {
+                Operation::Write(bytes) => bytes.iter().copied(),
+                Operation::Read(_) => [].iter().copied(),
+            })
+            .collect();
+        let (written, result) = match fault {
+            Some(fault @ Fault::NackData { after }) => (
+                &bytes[..after.min(bytes.len())],
+                Err(SimError(fault.kind())),
+            ),
+            _ => (&bytes[..], Ok(())),
+        };
+        if !written.is_empty() {
+            self.bus_log.push((address, written.to_vec()));
+        }
+        if device {
+            self.decode(written);
+        }
+        result
+    }
+}
+
+impl ErrorType for Simulator {
+    type Error = SimError;
+}
+
+impl i2c::I2c for Simulator {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.process(address, operations)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for Simulator {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.process(address, operations)
+    }
+}
+
+/// Test doubles of the peripherals around the device
+#[cfg(test)]
+pub(crate) mod doubles {
+    use embedded_hal::digital::{self, ErrorKind, OutputPin};
+
+    /// Delay adding up the requested waits instead of waiting
+    #[derive(Debug, Default)]
+    pub(crate) struct TotalDelay {
+        pub(crate) ns: u64,
+    }
+
+    impl embedded_hal::delay::DelayNs for TotalDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.ns += ns as u64;
+        }
+    }
+
+    impl embedded_hal_async::delay::DelayNs for TotalDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.ns += ns as u64;
+        }
+    }
+
+    /// Output pin remembering its level, failing every change while `broken`
+    #[derive(Debug, Default)]
+    pub(crate) struct MockPin {
+        pub(crate) high: bool,
+        pub(crate) broken: bool,
+    }
+
+    impl digital::ErrorType for MockPin {
+        type Error = ErrorKind;
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            if self.broken {
+                return Err(ErrorKind::Other);
+            }
+            self.high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            if self.broken {
+                return Err(ErrorKind::Other);
+            }
+            self.high = true;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::i2c::I2c;
+
+    use super::doubles::{MockPin, TotalDelay};
+    use super::*;
+    use crate::{
+        ColorOrder, Error, FrameBuffer, PixelMap, PowerState, Rgb, RgbView, SDB_WAKE_TIME_US,
+    };
+
+    /// Register behavior of the simulator, run against both drivers
+    macro_rules! driver_tests {
+        ($run:path) => {
+            #[test]
+            fn staged_values_show_after_update() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.enable_all()).unwrap();
+                $run(driver.set_brightness(0, 100)).unwrap();
+                let sim = driver.release();
+                assert_eq!(sim.register(PWM), Some(100));
+                assert_eq!(sim.duty(Channel::Out1), 0);
+                let mut driver = Driver::new(sim);
+                $run(driver.commit()).unwrap();
+                assert_eq!(driver.release().duty(Channel::Out1), 100);
+            }
+
+            #[test]
+            fn shutdown_masks_outputs() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.set_all(&[7; 18])).unwrap();
+                $run(driver.shutdown_device()).unwrap();
+                let sim = driver.release();
+                assert!(sim.is_shutdown());
+                assert_eq!(sim.duties(), [0; 18]);
+                assert_eq!(sim.pwm(Channel::Out18), 7);
+                let mut driver = Driver::new(sim);
+                $run(driver.enable_device()).unwrap();
+                assert_eq!(driver.release().duties(), [7; 18]);
+            }
+
+            #[test]
+            fn reset_restores_power_on_state() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.set_all(&[7; 18])).unwrap();
+                $run(driver.reset()).unwrap();
+                let sim = driver.release();
+                assert!(sim.is_shutdown());
+                assert_eq!(sim.register(PWM), Some(0));
+                assert!(!sim.is_enabled(Channel::Out1));
+                assert_eq!(sim.log().last().unwrap(), &[RESET, 0]);
+            }
+
+            #[test]
+            fn replay_restores_reattached_device() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                driver.set_resilient(true);
+                $run(driver.init(false)).unwrap();
+                $run(driver.set_all(&[5; 18])).unwrap();
+                driver.i2c_mut().detach();
+                assert!($run(driver.set_brightness(0, 9)).is_err());
+                assert!(!driver.is_synced());
+                driver.i2c_mut().attach();
+                assert_eq!(driver.i2c_mut().duties(), [0; 18]);
+                $run(driver.recover()).unwrap();
+                assert!(driver.is_synced());
+                let mut duties = [5; 18];
+                duties[0] = 9;
+                assert_eq!(driver.release().duties(), duties);
+            }
+
+            #[test]
+            fn marked_device_is_replayed_before_next_write() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.set_all(&[5; 18])).unwrap();
+                // Replugged while idle, no write failed
+                driver.i2c_mut().attach();
+                driver.mark_unsynced();
+                assert!(!driver.is_synced());
+                $run(driver.set(Channel::Out2, 6)).unwrap();
+                assert!(driver.is_synced());
+                let mut duties = [5; 18];
+                duties[1] = 6;
+                assert_eq!(driver.release().duties(), duties);
+            }
+
+            #[test]
+            fn failed_writes_are_not_replayed_unless_resilient() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                driver.i2c_mut().fail_next(Fault::NackAddress);
+                assert!($run(driver.set_all(&[5; 18])).is_err());
+                assert!(driver.is_synced());
+                driver.i2c_mut().clear_log();
+                $run(driver.set(Channel::Out1, 1)).unwrap();
+                assert_eq!(driver.release().log(), [[PWM, 1], [UPDATE, 0]]);
+            }
+
+            #[test]
+            fn commit_writes_one_span_and_latches_once() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                driver.i2c_mut().clear_log();
+                let mut tx = driver.begin();
+                tx.set(Channel::Out2, 2)
+                    .set(Channel::Out4, 4)
+                    .enable_channel(Channel::Out2)
+                    .enable_channel(Channel::Out4);
+                $run(tx.commit()).unwrap();
+                let sim = driver.release();
+                assert_eq!(sim.log().len(), 2);
+                assert_eq!(sim.log()[0][..5], [PWM + 1, 2, 0, 4, 0]);
+                assert_eq!(sim.log()[0].len(), 1 + usize::from(LED_CONTROL - PWM));
+                assert_eq!(sim.log()[1], [UPDATE, 0]);
+                assert_eq!(sim.duty(Channel::Out2), 2);
+                assert_eq!(sim.duty(Channel::Out4), 4);
+            }
+
+            #[test]
+            fn invalid_transactions_write_nothing() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                driver.i2c_mut().clear_log();
+                let mut tx = driver.begin();
+                tx.set(Channel::Out1, 1)
+                    .set_many(Channel::Out17, &[1, 2, 3]);
+                assert!(matches!($run(tx.commit()), Err(Error::Address)));
+                let mut tx = driver.begin();
+                tx.set(Channel::Out1, 1).set_enable_mask(1 << 18);
+                assert!(matches!($run(tx.commit()), Err(Error::Address)));
+                assert!(driver.release().log().is_empty());
+            }
+
+            #[test]
+            fn unchanged_commit_writes_nothing() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.set(Channel::Out1, 1)).unwrap();
+                driver.i2c_mut().clear_log();
+                $run(driver.begin().commit()).unwrap();
+                let mut tx = driver.begin();
+                tx.set(Channel::Out1, 1);
+                $run(tx.commit()).unwrap();
+                assert!(driver.release().log().is_empty());
+            }
+
+            #[test]
+            fn failed_commit_can_be_retried() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                driver.i2c_mut().fail_next(Fault::NackAddress);
+                let mut tx = driver.begin();
+                tx.set(Channel::Out2, 9).enable_channel(Channel::Out2);
+                assert!($run(tx.commit()).is_err());
+                // A failed latch leaves the device unchanged as well
+                let latch = driver.i2c_mut().transaction_count() + 1;
+                driver.i2c_mut().fail_nth(latch, Fault::Bus);
+                let mut tx = driver.begin();
+                tx.set(Channel::Out2, 9).enable_channel(Channel::Out2);
+                assert!($run(tx.commit()).is_err());
+                let mut tx = driver.begin();
+                tx.set(Channel::Out2, 9).enable_channel(Channel::Out2);
+                $run(tx.commit()).unwrap();
+                assert_eq!(driver.release().duty(Channel::Out2), 9);
+            }
+
+            #[test]
+            fn failed_flush_can_be_retried() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                let mut frame = FrameBuffer::new();
+                frame.set(Channel::Out1, 10);
+                frame.set(Channel::Out18, 18);
+                driver.i2c_mut().fail_next(Fault::NackAddress);
+                assert!($run(driver.flush(&frame)).is_err());
+                // Fail the second range, after the first one was written
+                let second = driver.i2c_mut().transaction_count() + 1;
+                driver.i2c_mut().fail_nth(second, Fault::Bus);
+                assert!($run(driver.flush(&frame)).is_err());
+                driver.i2c_mut().clear_log();
+                $run(driver.flush(&frame)).unwrap();
+                let sim = driver.release();
+                assert!(!sim.log().is_empty());
+                assert_eq!(sim.duty(Channel::Out1), 10);
+                assert_eq!(sim.duty(Channel::Out18), 18);
+            }
+
+            #[test]
+            fn pixels_are_written_once_and_latched_once() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.enable_all()).unwrap();
+                driver.i2c_mut().clear_log();
+                let map = PixelMap::consecutive(ColorOrder::Grb);
+                let mut view = RgbView::new(&mut driver, map);
+                $run(view.set_pixel(1, Rgb::new(10, 20, 30))).unwrap();
+                let sim = driver.release();
+                assert_eq!(sim.log(), [&[PWM + 3, 20, 10, 30][..], &[UPDATE, 0]]);
+                assert_eq!(sim.duty(Channel::Out4), 20);
+                assert_eq!(sim.duty(Channel::Out5), 10);
+                assert_eq!(sim.duty(Channel::Out6), 30);
+            }
+
+            #[test]
+            fn pixels_out_of_bounds_are_rejected() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                let mut view = RgbView::new(&mut driver, PixelMap::default());
+                let color = Rgb::new(1, 2, 3);
+                assert!(matches!(
+                    $run(view.set_pixel(6, color)),
+                    Err(Error::Address)
+                ));
+                let result = $run(view.set_pixels(usize::MAX, &[color]));
+                assert!(matches!(result, Err(Error::Address)));
+                assert!(driver.release().log().is_empty());
+            }
+
+            #[test]
+            fn sdb_pin_controls_hardware_shutdown() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::with_sdb(&mut sim, MockPin::default());
+                let mut delay = TotalDelay::default();
+                $run(driver.init(false)).unwrap();
+                assert_eq!(driver.power_state(), PowerState::Normal);
+                driver.hw_shutdown().unwrap();
+                assert_eq!(driver.power_state(), PowerState::HardwareShutdown);
+                $run(driver.hw_wake(&mut delay)).unwrap();
+                assert_eq!(driver.power_state(), PowerState::Normal);
+                assert_eq!(delay.ns, u64::from(SDB_WAKE_TIME_US) * 1000);
+                let (_, pin) = driver.release_with_sdb();
+                assert!(pin.high);
+            }
+
+            #[test]
+            fn sdb_pin_failure_keeps_power_state() {
+                let mut sim = Simulator::new();
+                let pin = MockPin {
+                    high: true,
+                    broken: true,
+                };
+                let mut driver = Driver::with_sdb(&mut sim, pin);
+                let mut delay = TotalDelay::default();
+                assert!(matches!(driver.hw_shutdown(), Err(Error::Pin)));
+                assert_eq!(driver.power_state(), PowerState::SoftwareShutdown);
+                assert!(matches!($run(driver.hw_wake(&mut delay)), Err(Error::Pin)));
+                assert_eq!(delay.ns, 0);
+            }
+
+            #[test]
+            fn probe_reports_missing_device_as_conn() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.probe()).unwrap();
+                driver.i2c_mut().detach();
+                assert!(matches!($run(driver.probe()), Err(Error::Conn)));
+                driver.i2c_mut().attach();
+                driver.i2c_mut().fail_next(Fault::NackAddress);
+                assert!(matches!($run(driver.probe()), Err(Error::Conn)));
+            }
+
+            #[test]
+            fn probe_keeps_other_bus_errors() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                driver.i2c_mut().fail_next(Fault::Bus);
+                let result = $run(driver.probe());
+                assert!(matches!(result, Err(Error::I2c(SimError(ErrorKind::Bus)))));
+            }
+
+            #[test]
+            fn init_stops_before_reset_without_device() {
+                let mut sim = Simulator::new();
+                sim.detach();
+                let mut driver = Driver::new(&mut sim);
+                assert!(matches!($run(driver.init(true)), Err(Error::Conn)));
+                let sim = driver.release();
+                assert_eq!(sim.transaction_count(), 1);
+                assert!(sim.log().is_empty());
+            }
+
+            #[test]
+            fn enabling_channels_keeps_the_others() {
+                let mut sim = Simulator::new();
+                let mut driver = Driver::new(&mut sim);
+                $run(driver.init(false)).unwrap();
+                $run(driver.enable_channel(Channel::Out1)).unwrap();
+                $run(driver.enable_channel(Channel::Out2)).unwrap();
+                let sim = driver.release();
+                assert!(sim.is_enabled(Channel::Out1));
+                assert!(sim.is_enabled(Channel::Out2));
+                assert!(!sim.is_enabled(Channel::Out3));
+            }
+        };
+    }
+
+    mod nonblocking {
+        use super::*;
+        use crate::{Is31Fl3218 as Driver, LedDriver};
+
+        driver_tests!(embassy_futures::block_on);
+    }
+
+    #[cfg(feature = "blocking")]
+    mod blocking {
+        use super::*;
+        use crate::blocking::{Is31Fl3218 as Driver, LedDriver};
+
+        driver_tests!(core::convert::identity);
+    }
+
+    #[test]
+    fn data_nack_applies_acknowledged_bytes() {
+        let mut sim = Simulator::new();
+        sim.fail_next(Fault::NackData { after: 3 });
+        let result = sim.write(DEVICE_ADDRESS, &[PWM, 1, 2, 3, 4]);
+        assert_eq!(result, Err(SimError(Fault::NackData { after: 3 }.kind())));
+        assert_eq!(sim.register(PWM), Some(1));
+        assert_eq!(sim.register(PWM + 1), Some(2));
+        assert_eq!(sim.register(PWM + 2), Some(0));
+        assert_eq!(sim.log(), [[PWM, 1, 2]]);
+
+        sim.fail_next(Fault::NackData { after: 0 });
+        assert!(sim.write(DEVICE_ADDRESS, &[PWM, 9]).is_err());
+        assert_eq!(sim.register(PWM), Some(1));
+        assert_eq!(sim.log().len(), 1);
+    }
+
+    #[test]
+    fn data_nack_can_hit_the_update_register() {
+        let mut sim = Simulator::new();
+        sim.fail_next(Fault::NackData { after: 23 });
+        let mut bytes = [0x3f; 23];
+        bytes[0] = PWM;
+        assert!(sim.write(DEVICE_ADDRESS, &bytes).is_err());
+        assert_eq!(sim.pwm(Channel::Out18), 0x3f);
+    }
+
+    #[test]
+    fn register_is_none_beyond_shadow() {
+        let mut sim = Simulator::new();
+        sim.write(DEVICE_ADDRESS, &[LED_CONTROL + 2, 0x3f]).unwrap();
+        assert_eq!(sim.register(LED_CONTROL + 2), Some(0x3f));
+        assert_eq!(sim.register(UPDATE), None);
+        assert_eq!(sim.register(0xff), None);
+    }
+
+    #[test]
+    fn reads_and_other_addresses_are_not_acknowledged() {
+        let mut sim = Simulator::new();
+        let nack = SimError(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        assert_eq!(sim.read(DEVICE_ADDRESS, &mut [0]), Err(nack));
+        assert_eq!(sim.write(0x55, &[PWM, 1]), Err(nack));
+        assert!(sim.log().is_empty());
+    }
+}
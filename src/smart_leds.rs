@@ -0,0 +1,60 @@
+//! `smart-leds` traits for the RGB pixels, writing all channels with `set_all`
+
+use embedded_hal_async::i2c::I2c;
+use smart_leds_trait::{SmartLedsWriteAsync, RGB8};
+
+use crate::{Error, Is31Fl3218, Rgb, RgbView};
+
+impl From<RGB8> for Rgb {
+    fn from(color: RGB8) -> Self {
+        Self::new(color.r, color.g, color.b)
+    }
+}
+
+impl From<Rgb> for RGB8 {
+    fn from(color: Rgb) -> Self {
+        Self::new(color.r, color.g, color.b)
+    }
+}
+
+impl<I2C, E, SDB> SmartLedsWriteAsync for RgbView<'_, Is31Fl3218<I2C, SDB>>
+where
+    I2C: I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    type Error = Error<E>;
+    type Color = RGB8;
+
+    async fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let values = self
+            .map
+            .render(iterator.into_iter().map(|color| color.into().into()));
+        self.driver.set_all(&values).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I2C, E, SDB> smart_leds_trait::SmartLedsWrite
+    for RgbView<'_, crate::blocking::Is31Fl3218<I2C, SDB>>
+where
+    I2C: embedded_hal::i2c::I2c<Error = E>,
+    E: Into<Error<E>>,
+{
+    type Error = Error<E>;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let values = self
+            .map
+            .render(iterator.into_iter().map(|color| color.into().into()));
+        self.driver.set_all(&values)
+    }
+}
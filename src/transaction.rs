@@ -0,0 +1,88 @@
+use crate::shadow::Shadow;
+use crate::Channel;
+
+/// Staged PWM and enable changes, sent to the device with a single latch
+///
+/// Created by `begin()` on a driver. Nothing is written until `commit()` is called,
+/// dropping the transaction discards all staged changes.
+pub struct Transaction<'a, D> {
+    /// Driver the changes are committed to
+    pub(crate) driver: &'a mut D,
+    /// Register values after the commit
+    pub(crate) staged: Shadow,
+    /// Set when an out of bounds change was staged, fails the commit
+    pub(crate) invalid: bool,
+}
+
+impl<'a, D> Transaction<'a, D> {
+    pub(crate) fn new(driver: &'a mut D, staged: Shadow) -> Self {
+        Self {
+            driver,
+            staged,
+            invalid: false,
+        }
+    }
+
+    /// Stage a brightness value for one channel
+    pub fn set(&mut self, channel: Channel, brightness: u8) -> &mut Self {
+        self.staged.set_pwm(channel, brightness);
+        self
+    }
+
+    /// Stage brightness values for many consecutive channels starting at `start`
+    pub fn set_many(&mut self, start: Channel, values: &[u8]) -> &mut Self {
+        if start.index() + values.len() > Channel::COUNT {
+            self.invalid = true;
+        } else {
+            self.staged.set_pwm_many(start.index(), values);
+        }
+        self
+    }
+
+    /// Stage brightness values for all channels
+    pub fn set_all(&mut self, values: &[u8; 18]) -> &mut Self {
+        self.staged.set_pwm_many(0, values);
+        self
+    }
+
+    /// Stage enabling a channel
+    pub fn enable_channel(&mut self, channel: Channel) -> &mut Self {
+        self.staged.set_enabled(channel, true);
+        self
+    }
+
+    /// Stage disabling a channel
+    pub fn disable_channel(&mut self, channel: Channel) -> &mut Self {
+        self.staged.set_enabled(channel, false);
+        self
+    }
+
+    /// Stage toggling a channel
+    pub fn toggle_channel(&mut self, channel: Channel) -> &mut Self {
+        self.staged.toggle_enabled(channel);
+        self
+    }
+
+    /// Stage enabling all channels
+    pub fn enable_all(&mut self) -> &mut Self {
+        self.staged.set_enabled_all(true);
+        self
+    }
+
+    /// Stage disabling all channels
+    pub fn disable_all(&mut self) -> &mut Self {
+        self.staged.set_enabled_all(false);
+        self
+    }
+
+    /// Stage the enable state of all channels
+    /// Bit 0 of `mask` controls the first channel, bit 17 the last one
+    pub fn set_enable_mask(&mut self, mask: u32) -> &mut Self {
+        if mask >> 18 != 0 {
+            self.invalid = true;
+        } else {
+            self.staged.set_enable_mask(mask);
+        }
+        self
+    }
+}